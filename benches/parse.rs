@@ -0,0 +1,137 @@
+//! Compares the single-pass parser with the previous `uriparse` round-trip
+//!
+//! Run with `cargo bench`.
+
+use core::convert::{TryFrom, TryInto};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use pkcs11_uri::{Pkcs11Uri, Pkcs11UriRef};
+
+const URIS: &[(&str, &str)] = &[
+    (
+        "short",
+        "pkcs11:token=my-ca;object=my-signing-key;type=private",
+    ),
+    (
+        "long",
+        "pkcs11:library-manufacturer=Snake%20Oil,%20Inc.;library-version=3;\
+            token=The%20Software%20PKCS%2311%20Softtoken;serial=DECC0401648;\
+            id=%69%95%3E%5C%F4%BD%EC%91;object=my-signing-key;type=private\
+            ?pin-source=file:/etc/token&module-path=/usr/lib/softhsm/libsofthsm2.so",
+    ),
+];
+
+/// A decoded attribute value, as the previous parser stored it
+#[allow(dead_code)]
+enum Value {
+    String(String),
+    Bytes(Vec<u8>),
+    SlotId(u64),
+    Version(u8, u8),
+    Serial([u8; 16]),
+    Class(u8),
+}
+
+/// Values by position of their name
+type Attributes = Vec<Option<Value>>;
+
+const PATH_NAMES: &[&str] = &[
+    "library-description",
+    "library-manufacturer",
+    "library-version",
+    "slot-description",
+    "slot-id",
+    "slot-manufacturer",
+    "manufacturer",
+    "model",
+    "token",
+    "serial",
+    "type",
+    "id",
+    "object",
+];
+const QUERY_NAMES: &[&str] = &["pin-source", "pin-value", "module-name", "module-path"];
+
+/// The previous attribute parser: split at the delimiter and at `=`, then decode
+fn split_attributes(input: &str, delimiter: char, names: &[&str]) -> Option<Attributes> {
+    let mut attributes: Attributes = names.iter().map(|_| None).collect();
+    if input.is_empty() {
+        return Some(attributes);
+    }
+    for component in input.split(delimiter) {
+        let mut tuple = component.splitn(2, '=');
+        let (key, value) = (tuple.next()?, tuple.next()?);
+        let index = names.iter().position(|name| *name == key)?;
+        let decoded = || percent_encoding::percent_decode_str(value);
+        let value = match key {
+            "id" => Value::Bytes(decoded().collect()),
+            "slot-id" => Value::SlotId(value.parse().ok()?),
+            "library-version" => {
+                let mut numbers = value.splitn(2, '.');
+                let major = numbers.next()?.parse().ok()?;
+                let minor = numbers.next().map_or(Some(0), |minor| minor.parse().ok())?;
+                Value::Version(major, minor)
+            }
+            "serial" => {
+                let mut serial: Vec<u8> = decoded().collect();
+                if serial.len() > 16 {
+                    return None;
+                }
+                serial.resize(16, b' ');
+                Value::Serial(serial.try_into().ok()?)
+            }
+            "type" => Value::Class(match value {
+                "data" => 0,
+                "cert" => 1,
+                "public" => 2,
+                "private" => 3,
+                "secret-key" => 4,
+                _ => return None,
+            }),
+            _ => Value::String(decoded().decode_utf8().ok()?.into_owned()),
+        };
+        if attributes[index].replace(value).is_some() {
+            return None;
+        }
+    }
+    Some(attributes)
+}
+
+/// The parser before the single-pass scanner, reproduced: strip whitespace, parse a
+/// generic URI with `uriparse`, then split the path and query into attributes
+fn parse_with_uriparse(uri_str: &str) -> Option<(Attributes, Attributes)> {
+    let uri_string: String = uri_str.chars().filter(|c| !c.is_whitespace()).collect();
+    let uri = uriparse::URIReference::try_from(uri_string.as_str()).ok()?;
+    if uri.scheme() != Some(&uriparse::Scheme::PKCS11) || uri.authority().is_some() {
+        return None;
+    }
+    if uri.path().segments().len() != 1 {
+        return None;
+    }
+    let segment = uri.path().segments()[0].as_str();
+    let path_attributes = split_attributes(segment, ';', PATH_NAMES)?;
+    let query = uri.query().map(|query| query.as_str()).unwrap_or("");
+    let query_attributes = split_attributes(query, '&', QUERY_NAMES)?;
+    Some((path_attributes, query_attributes))
+}
+
+fn parse(c: &mut Criterion) {
+    for (name, uri) in URIS.iter().copied() {
+        assert!(parse_with_uriparse(uri).is_some());
+
+        let mut group = c.benchmark_group(name);
+        group.bench_function("uriparse", |b| {
+            b.iter(|| parse_with_uriparse(black_box(uri)))
+        });
+        group.bench_function("Pkcs11Uri", |b| {
+            b.iter(|| Pkcs11Uri::try_from(black_box(uri)))
+        });
+        group.bench_function("Pkcs11UriRef", |b| {
+            b.iter(|| Pkcs11UriRef::try_from(black_box(uri)))
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
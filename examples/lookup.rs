@@ -29,7 +29,8 @@ fn try_main() -> anyhow::Result<()> {
             ?pin-value=1234
             &module-path=/usr/lib/libsofthsm2.so";
     let uri = Pkcs11Uri::try_from(_uri_str)?;
-    let (context, session, object) = uri.identify_object().unwrap();
+    let (session, object) = uri.identify_object()?;
+    let context = session.module();
 
     //  CKM_SHA256_RSA_PKCS
     let mechanism = pkcs11::types::CK_MECHANISM {
@@ -40,9 +41,11 @@ fn try_main() -> anyhow::Result<()> {
     };
 
     // now do a signature, assuming this is an RSA key
-    context.sign_init(session, &mechanism, object).unwrap();
+    context
+        .sign_init(session.handle(), &mechanism, object)
+        .unwrap();
     let data = String::from("PKCS #11 is pretty horrible").into_bytes();
-    let signature = context.sign(session, &data).unwrap();
+    let signature = context.sign(session.handle(), &data).unwrap();
 
     println!("signature: {:x?}", signature.as_slice());
     assert_eq!(signature.len(), 256);
@@ -16,7 +16,7 @@ fn main() {
 fn sha256(data: &[u8]) -> [u8; 32] {
     use sha2::Digest;
     let mut hasher = sha2::Sha256::new();
-    hasher.update(&data);
+    hasher.update(data);
     let mut digest = [0u8; 32];
     digest.copy_from_slice(&hasher.finalize());
     digest
@@ -30,7 +30,8 @@ fn try_main() -> anyhow::Result<()> {
             ?pin-value=1234
             &module-path=/usr/lib/libsofthsm2.so";
     let uri = Pkcs11Uri::try_from(uri_str)?;
-    let (context, session, object) = uri.identify_object().unwrap();
+    let (session, object) = uri.identify_object()?;
+    let context = session.module();
 
     //  CKM_SHA256_RSA_PKCS
     let mechanism = pkcs11::types::CK_MECHANISM {
@@ -40,9 +41,13 @@ fn try_main() -> anyhow::Result<()> {
     };
 
     // now do a signature, assuming this is an RSA key
-    context.sign_init(session, &mechanism, object).unwrap();
+    context
+        .sign_init(session.handle(), &mechanism, object)
+        .unwrap();
+    // keys with CKA_ALWAYS_AUTHENTICATE need a PIN for every signature
+    uri.authenticate(&session, object)?;
     let data = String::from("PKCS #11 is pretty horrible").into_bytes();
-    let signature = context.sign(session, &data).unwrap();
+    let signature = context.sign(session.handle(), &data).unwrap();
 
     println!(
         "signature: \n{}",
@@ -59,7 +64,7 @@ fn try_main() -> anyhow::Result<()> {
     let mut template = vec![n_attribute, e_attribute];
 
     let (rv, attributes) = context
-        .get_attribute_value(session, object, &mut template)
+        .get_attribute_value(session.handle(), object, &mut template)
         .unwrap();
     assert_eq!(rv, 0);
     let n = attributes[0].get_biginteger().unwrap();
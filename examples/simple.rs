@@ -6,7 +6,7 @@ fn main() {
         std::process::exit(1);
     }
 }
-fn try_main() -> anyhow::Result<()> {
+fn try_main() -> Result<(), pkcs11_uri::Pkcs11UriError> {
     let uri_str = "pkcs11:library-version=3;token=The%20Software%20PKCS%2311%20Softtoken;id=%69%95%3E%5C%F4%BD%EC%91;object=my-signing-key;type=private;slot-id=327;serial=DECC0401648?pin-source=file:/etc/token";
     // let uri = "pkcs11:object=my-signing-key;type=private;serial=DECC0401648?pin-source=file:/etc/token&x=y";
     // let uri = "pkcs11:object=my-signing-key;type=private;serial=DECC0401648";
@@ -0,0 +1,112 @@
+use core::convert::TryFrom;
+use core::fmt;
+
+use crate::parser::{Component, Scanner};
+use crate::{ParseOptions, PathAttributesRef, Pkcs11Uri, Pkcs11UriError, QueryAttributesRef};
+
+/// PKCS #11 URI borrowed from its input
+///
+/// Parsing checks the whole URI without allocating; attribute values are only
+/// percent-decoded when read, and borrow from the input unless they contain escapes.
+/// Unlike [`Pkcs11Uri::try_from`], line folding is not removed, so the input must
+/// not contain whitespace.
+///
+/// ```
+/// use pkcs11_uri::Pkcs11UriRef;
+///
+/// let uri = Pkcs11UriRef::try_from("pkcs11:token=my%20token;object=my-key").unwrap();
+/// assert_eq!(uri.path_attributes.token_label().unwrap(), "my token");
+/// assert_eq!(uri.path_attributes.object_label().unwrap(), "my-key");
+/// assert_eq!(uri.to_owned().path_attributes.object_label.unwrap(), "my-key");
+/// ```
+#[derive(Clone, Copy)]
+pub struct Pkcs11UriRef<'a> {
+    pub path_attributes: PathAttributesRef<'a>,
+    pub query_attributes: QueryAttributesRef<'a>,
+    uri: &'a str,
+}
+
+/// Leaves out the input, which may contain `pin-value`
+impl fmt::Debug for Pkcs11UriRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pkcs11UriRef")
+            .field("path_attributes", &self.path_attributes)
+            .field("query_attributes", &self.query_attributes)
+            .finish()
+    }
+}
+
+impl<'a> Pkcs11UriRef<'a> {
+    /// TryFrom as inherent method, with [`ParseOptions::default`]
+    pub fn try_from(uri: &'a str) -> Result<Self, Pkcs11UriError> {
+        Self::parse_with(uri, ParseOptions::default())
+    }
+
+    /// Parses a URI following `options`, except for line folding
+    pub fn parse_with(uri: &'a str, options: ParseOptions) -> Result<Self, Pkcs11UriError> {
+        const SCHEME: &str = "pkcs11:";
+        let is_pkcs11 = uri
+            .get(..SCHEME.len())
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case(SCHEME));
+        if !is_pkcs11 {
+            return Err(Pkcs11UriError::WrongScheme { offset: 0 });
+        }
+
+        if uri[SCHEME.len()..].starts_with("//") {
+            return Err(Pkcs11UriError::UnexpectedAuthority {
+                offset: SCHEME.len(),
+            });
+        }
+
+        let mut path_attributes = PathAttributesRef::default();
+        let mut query_attributes = QueryAttributesRef::default();
+        let mut scanner = Scanner::uri(uri, SCHEME.len(), options);
+        while let Some(item) = scanner.next() {
+            let (component, attribute) = item?;
+            match component {
+                Component::Path => {
+                    let preceding = &uri[SCHEME.len()..attribute.offset];
+                    path_attributes.insert(attribute, preceding, options)?;
+                }
+                Component::Query => {
+                    let preceding = &uri[scanner.query_start().unwrap_or(0)..attribute.offset];
+                    query_attributes.insert(attribute, preceding, options)?;
+                }
+            }
+        }
+        match scanner.query_start() {
+            Some(query_start) => {
+                path_attributes.input = &uri[SCHEME.len()..query_start - 1];
+                query_attributes.input = &uri[query_start..];
+            }
+            None => path_attributes.input = &uri[SCHEME.len()..],
+        }
+
+        Ok(Pkcs11UriRef {
+            path_attributes,
+            query_attributes,
+            uri,
+        })
+    }
+
+    /// The URI as given
+    pub fn as_str(&self) -> &'a str {
+        self.uri
+    }
+
+    /// Decodes all attributes into an owned [`Pkcs11Uri`]
+    pub fn to_owned(&self) -> Pkcs11Uri {
+        Pkcs11Uri {
+            path_attributes: self.path_attributes.to_owned(),
+            query_attributes: self.query_attributes.to_owned(),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Pkcs11UriRef<'a> {
+    type Error = Pkcs11UriError;
+
+    fn try_from(uri: &'a str) -> Result<Self, Self::Error> {
+        Self::try_from(uri)
+    }
+}
@@ -0,0 +1,189 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{
+    is_vendor_attribute_name, ObjectClass, PathAttributes, Pkcs11Uri, Pkcs11UriError,
+    QueryAttributes, SecretPin, SlotId, Version,
+};
+
+/// Builder for [`Pkcs11Uri`] values
+///
+/// Values are given unencoded; percent-encoding happens when the URI is displayed.
+///
+/// ```
+/// use pkcs11_uri::{ObjectClass, Pkcs11Uri};
+///
+/// let uri = Pkcs11Uri::builder()
+///     .token_label("my-ca")
+///     .object_label("my signing key")
+///     .object_class(ObjectClass::PrivateKey)
+///     .module_path("/usr/lib/libsofthsm2.so")
+///     .build()
+///     .unwrap();
+/// assert_eq!(
+///     uri.to_string(),
+///     "pkcs11:token=my-ca;type=private;object=my%20signing%20key\
+///         ?module-path=/usr/lib/libsofthsm2.so"
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Pkcs11UriBuilder {
+    path_attributes: PathAttributes,
+    query_attributes: QueryAttributes,
+    // checked and padded in `build`
+    token_serial: Option<Vec<u8>>,
+}
+
+macro_rules! setters {
+    ($attributes:ident: $($attribute:ident($value:ty) = $name:literal,)*) => { $(
+        #[doc = concat!("Sets the `", $name, "` attribute")]
+        pub fn $attribute(mut self, value: impl Into<$value>) -> Self {
+            self.$attributes.$attribute = Some(value.into());
+            self
+        }
+    )* }
+}
+
+impl Pkcs11UriBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    setters! { path_attributes:
+        library_description(String) = "library-description",
+        library_manufacturer(String) = "library-manufacturer",
+        library_version(Version) = "library-version",
+
+        slot_description(String) = "slot-description",
+        slot_id(SlotId) = "slot-id",
+        slot_manufacturer(String) = "slot-manufacturer",
+
+        token_manufacturer(String) = "manufacturer",
+        token_model(String) = "model",
+        token_label(String) = "token",
+
+        object_class(ObjectClass) = "type",
+        object_id(Vec<u8>) = "id",
+        object_label(String) = "object",
+    }
+
+    setters! { query_attributes:
+        pin_source(String) = "pin-source",
+        pin_value(SecretPin) = "pin-value",
+        module_name(String) = "module-name",
+        module_path(String) = "module-path",
+    }
+
+    /// Sets the `serial` attribute, at most 16 bytes
+    pub fn token_serial(mut self, value: impl AsRef<[u8]>) -> Self {
+        self.token_serial = Some(value.as_ref().to_vec());
+        self
+    }
+
+    /// Sets a vendor-specific (`x-` prefixed) path attribute
+    pub fn path_vendor_attribute(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.path_attributes
+            .vendor
+            .insert(name.into(), value.into());
+        self
+    }
+
+    /// Sets a vendor-specific (`x-` prefixed) query attribute
+    pub fn query_vendor_attribute(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.query_attributes
+            .vendor
+            .insert(name.into(), value.into());
+        self
+    }
+
+    /// Checks the attributes against RFC 7512; error offsets are always zero
+    pub fn build(self) -> Result<Pkcs11Uri, Pkcs11UriError> {
+        let Pkcs11UriBuilder {
+            mut path_attributes,
+            query_attributes,
+            token_serial,
+        } = self;
+
+        if let Some(token_serial) = token_serial {
+            if token_serial.len() > 16 {
+                return Err(Pkcs11UriError::SerialTooLong {
+                    attribute: "serial".into(),
+                    offset: 0,
+                });
+            }
+            let mut serial = [b' '; 16];
+            serial[..token_serial.len()].copy_from_slice(&token_serial);
+            path_attributes.token_serial = Some(serial);
+        }
+
+        path_attributes.check()?;
+        query_attributes.check()?;
+
+        Ok(Pkcs11Uri {
+            path_attributes,
+            query_attributes,
+        })
+    }
+}
+
+impl PathAttributes {
+    /// Checks what the types allow but a URI cannot express; error offsets are zero
+    pub(crate) fn check(&self) -> Result<(), Pkcs11UriError> {
+        if let Some(object_class) = &self.object_class {
+            if !object_class.is_valid() {
+                return Err(Pkcs11UriError::InvalidValue {
+                    attribute: "type".into(),
+                    offset: 0,
+                });
+            }
+        }
+        check_vendor_names(&self.vendor)
+    }
+}
+
+impl QueryAttributes {
+    /// Checks what the types allow but a URI cannot express; error offsets are zero
+    pub(crate) fn check(&self) -> Result<(), Pkcs11UriError> {
+        let names = [
+            ("module-name", &self.module_name),
+            ("module-path", &self.module_path),
+        ];
+        for (name, value) in names.iter() {
+            if value.as_deref() == Some("") {
+                return Err(Pkcs11UriError::InvalidValue {
+                    attribute: (*name).into(),
+                    offset: 0,
+                });
+            }
+        }
+        check_vendor_names(&self.vendor)
+    }
+}
+
+fn check_vendor_names(vendor: &BTreeMap<String, Vec<u8>>) -> Result<(), Pkcs11UriError> {
+    for name in vendor.keys() {
+        if !is_vendor_attribute_name(name) {
+            return Err(Pkcs11UriError::UnknownAttribute {
+                attribute: name.clone(),
+                offset: 0,
+            });
+        }
+    }
+    Ok(())
+}
+
+impl Pkcs11Uri {
+    /// Starts building a URI from typed attribute values
+    pub fn builder() -> Pkcs11UriBuilder {
+        Pkcs11UriBuilder::new()
+    }
+}
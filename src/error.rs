@@ -0,0 +1,152 @@
+use alloc::string::String;
+use core::fmt;
+
+/// Errors returned when parsing a PKCS #11 URI
+///
+/// Offsets are byte offsets into the string that was passed to the parser.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pkcs11UriError {
+    /// The input is not a syntactically valid URI
+    InvalidSyntax { offset: usize },
+    /// The scheme is not `pkcs11`
+    WrongScheme { offset: usize },
+    /// The URI has an authority (`pkcs11://...`)
+    UnexpectedAuthority { offset: usize },
+    /// The attribute name is not defined by RFC 7512
+    UnknownAttribute { attribute: String, offset: usize },
+    /// The attribute occurs more than once
+    DuplicateAttribute { attribute: String, offset: usize },
+    /// A character that must be percent-encoded appears literally
+    InvalidCharacter { attribute: String, offset: usize },
+    /// A `%` is not followed by two hexadecimal digits
+    InvalidPercentEncoding { attribute: String, offset: usize },
+    /// The percent-decoded value of a textual attribute is not UTF-8
+    InvalidUtf8 { attribute: String, offset: usize },
+    /// The `slot-id` value is not a decimal number in range
+    InvalidSlotId { attribute: String, offset: usize },
+    /// The `serial` value is longer than 16 bytes
+    SerialTooLong { attribute: String, offset: usize },
+    /// The value is not allowed for this attribute (e.g. `type` or `library-version`)
+    InvalidValue { attribute: String, offset: usize },
+}
+
+impl Pkcs11UriError {
+    /// Byte offset in the input at which the error was detected
+    pub fn offset(&self) -> usize {
+        use Pkcs11UriError::*;
+        match self {
+            InvalidSyntax { offset }
+            | WrongScheme { offset }
+            | UnexpectedAuthority { offset }
+            | UnknownAttribute { offset, .. }
+            | DuplicateAttribute { offset, .. }
+            | InvalidCharacter { offset, .. }
+            | InvalidPercentEncoding { offset, .. }
+            | InvalidUtf8 { offset, .. }
+            | InvalidSlotId { offset, .. }
+            | SerialTooLong { offset, .. }
+            | InvalidValue { offset, .. } => *offset,
+        }
+    }
+
+    /// Name of the offending attribute, if the error concerns one
+    pub fn attribute(&self) -> Option<&str> {
+        use Pkcs11UriError::*;
+        match self {
+            InvalidSyntax { .. } | WrongScheme { .. } | UnexpectedAuthority { .. } => None,
+            UnknownAttribute { attribute, .. }
+            | DuplicateAttribute { attribute, .. }
+            | InvalidCharacter { attribute, .. }
+            | InvalidPercentEncoding { attribute, .. }
+            | InvalidUtf8 { attribute, .. }
+            | InvalidSlotId { attribute, .. }
+            | SerialTooLong { attribute, .. }
+            | InvalidValue { attribute, .. } => Some(attribute),
+        }
+    }
+
+    fn offset_mut(&mut self) -> &mut usize {
+        use Pkcs11UriError::*;
+        match self {
+            InvalidSyntax { offset }
+            | WrongScheme { offset }
+            | UnexpectedAuthority { offset }
+            | UnknownAttribute { offset, .. }
+            | DuplicateAttribute { offset, .. }
+            | InvalidCharacter { offset, .. }
+            | InvalidPercentEncoding { offset, .. }
+            | InvalidUtf8 { offset, .. }
+            | InvalidSlotId { offset, .. }
+            | SerialTooLong { offset, .. }
+            | InvalidValue { offset, .. } => offset,
+        }
+    }
+
+    /// Moves the offset, for errors from a parser that only saw part of the input
+    pub(crate) fn shifted(mut self, by: usize) -> Self {
+        *self.offset_mut() += by;
+        self
+    }
+
+    /// Replaces the offset, e.g. to map it back from a normalized to the original input
+    pub(crate) fn with_offset(mut self, offset: usize) -> Self {
+        *self.offset_mut() = offset;
+        self
+    }
+}
+
+impl fmt::Display for Pkcs11UriError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Pkcs11UriError::*;
+        match self {
+            InvalidSyntax { offset } => write!(f, "invalid URI syntax at offset {}", offset),
+            WrongScheme { offset } => {
+                write!(f, "URI should have PKCS11 scheme (offset {})", offset)
+            }
+            UnexpectedAuthority { offset } => {
+                write!(f, "URI should not have an authority (offset {})", offset)
+            }
+            UnknownAttribute { attribute, offset } => {
+                write!(f, "unknown attribute `{}` at offset {}", attribute, offset)
+            }
+            DuplicateAttribute { attribute, offset } => {
+                write!(
+                    f,
+                    "duplicate attribute `{}` at offset {}",
+                    attribute, offset
+                )
+            }
+            InvalidCharacter { attribute, offset } => write!(
+                f,
+                "character in `{}` at offset {} must be percent-encoded",
+                attribute, offset
+            ),
+            InvalidPercentEncoding { attribute, offset } => write!(
+                f,
+                "invalid percent-encoding in `{}` at offset {}",
+                attribute, offset
+            ),
+            InvalidUtf8 { attribute, offset } => {
+                write!(
+                    f,
+                    "value of `{}` at offset {} is not UTF-8",
+                    attribute, offset
+                )
+            }
+            InvalidSlotId { attribute, offset } => {
+                write!(f, "invalid `{}` at offset {}", attribute, offset)
+            }
+            SerialTooLong { attribute, offset } => write!(
+                f,
+                "`{}` at offset {} is longer than 16 bytes",
+                attribute, offset
+            ),
+            InvalidValue { attribute, offset } => {
+                write!(f, "invalid value for `{}` at offset {}", attribute, offset)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Pkcs11UriError {}
@@ -2,56 +2,279 @@
 //!
 //! Bare bones implementation of the [RFC 7512][rfc-7512] URI scheme for locating keys and other PKCS#11 objects.
 //!
-//! This library is patched together from existing libraries, namely `pkcs11`, `uriparse` and
-//! `percent-encoding`, and is a work in progress.
+//! URIs are checked against the RFC grammar by a single-pass scanner of its own, with
+//! `percent-encoding` for escapes; modules are loaded with `pkcs11`.
+//!
+//! The URI model and parser only need `alloc`; with default features disabled the crate
+//! is `no_std`. Loading PKCS #11 modules and looking up objects needs the default
+//! `runtime` feature.
 //!
 //! [rfc-7512]: https://tools.ietf.org/html/rfc7512
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 // use core::convert::TryFrom;
 // use core::convert::TryInto;
 use core::convert::{TryFrom, TryInto};
-
-use log::{debug, trace};
-pub type Context = pkcs11::Ctx;
-pub type SessionHandle = pkcs11::types::CK_SESSION_HANDLE;
-pub type ObjectHandle = pkcs11::types::CK_OBJECT_HANDLE;
-pub type SlotId = pkcs11::types::CK_SLOT_ID;
+use core::fmt;
+use core::ops::Range;
+
+use percent_encoding::{AsciiSet, CONTROLS};
+
+use log::debug;
+use zeroize::Zeroizing;
+
+/// `CK_ULONG` of the PKCS #11 C API, as in rust-pkcs11
+#[cfg(windows)]
+type CkUlong = u32;
+#[cfg(not(windows))]
+type CkUlong = u64;
+
+pub type SlotId = CkUlong;
+
+mod borrowed;
+pub use borrowed::Pkcs11UriRef;
+mod builder;
+pub use builder::Pkcs11UriBuilder;
+mod error;
+pub use error::Pkcs11UriError;
+mod parser;
+pub use parser::ParseOptions;
+use parser::{Attribute, Component, Scanner};
+mod secret;
+pub use secret::{RedactOptions, SecretPin};
+#[cfg(feature = "serde")]
+mod serialization;
+
+#[cfg(feature = "runtime")]
+mod modules;
+#[cfg(feature = "runtime")]
+pub use modules::{ModuleResolver, ModuleSource, ResolvedModule};
+#[cfg(feature = "runtime")]
+mod registry;
+#[cfg(feature = "runtime")]
+pub use registry::{Module, ModuleRegistry};
+#[cfg(feature = "runtime")]
+mod objects;
+#[cfg(feature = "runtime")]
+pub use objects::Objects;
+#[cfg(feature = "runtime")]
+mod pin;
+#[cfg(feature = "runtime")]
+pub use pin::{CommandPinProvider, EnvPinProvider, FilePinProvider, PinProvider, PinProviders};
+#[cfg(feature = "runtime")]
+mod runtime;
+#[cfg(feature = "runtime")]
+mod session;
+#[cfg(feature = "runtime")]
+pub use runtime::{Context, IdentifyOptions, ModuleSlot, ObjectHandle, SessionHandle, SlotPolicy};
+#[cfg(feature = "runtime")]
+pub use session::Session;
 
 #[cfg(test)]
 mod tests;
 
-use anyhow::anyhow;
+/// Characters other than ALPHA / DIGIT allowed unescaped in both components
+/// (the rest of `pk11-unreserved`, and `pk11-res-avail`)
+const PK11_RES_AVAIL: &[u8] = b"-._~:[]@!$'()*+,=";
+/// Additionally allowed in the path component (`pk11-path-res-avail`)
+const PK11_PATH_RES_AVAIL: &[u8] = b"&";
+/// Additionally allowed in the query component (`pk11-query-res-avail`)
+const PK11_QUERY_RES_AVAIL: &[u8] = b"/?|";
+
+const fn contains(bytes: &[u8], byte: u8) -> bool {
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == byte {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Whether `byte` may appear unescaped in an attribute value (`pk11-pchar` / `pk11-qchar`)
+const fn is_pk11_char(byte: u8, res_avail: &[u8]) -> bool {
+    byte.is_ascii_alphanumeric() || contains(PK11_RES_AVAIL, byte) || contains(res_avail, byte)
+}
+
+const fn pk11_encode_set(res_avail: &[u8]) -> AsciiSet {
+    let mut set = CONTROLS.add(b' ');
+    let mut byte = 0;
+    while byte < 0x80 {
+        if !is_pk11_char(byte, res_avail) {
+            set = set.add(byte);
+        }
+        byte += 1;
+    }
+    set
+}
+
+/// Characters that are percent-encoded in the path component (everything but `pk11-pchar`)
+const PK11_PATH: &AsciiSet = &pk11_encode_set(PK11_PATH_RES_AVAIL);
+
+/// Characters that are percent-encoded in the query component (everything but `pk11-qchar`)
+const PK11_QUERY: &AsciiSet = &pk11_encode_set(PK11_QUERY_RES_AVAIL);
 
-fn parse_slot_id(value: &str) -> Result<SlotId, &str> {
-    value.parse().or(Err(value))
+/// `pk11-v-pattr-nm` / `pk11-v-qattr-nm`: "x-" 1*(ALPHA / DIGIT / "-" / "_")
+pub(crate) fn is_vendor_attribute_name(key: &str) -> bool {
+    match key.strip_prefix("x-") {
+        Some(name) => {
+            !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+        }
+        None => false,
+    }
 }
 
-fn percent_decode_string(value: &str) -> Result<String, &str> {
-    Ok(percent_encoding::percent_decode_str(value)
-        .decode_utf8()
-        .or(Err(value))?
-        .into_owned())
+fn is_decimal(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|c| c.is_ascii_digit())
 }
 
-fn percent_decode_bytes(value: &str) -> Result<Vec<u8>, &str> {
-    Ok(percent_encoding::percent_decode_str(value).collect())
+fn parse_slot_id(name: &str, value: &str) -> Result<SlotId, Pkcs11UriError> {
+    let invalid = || Pkcs11UriError::InvalidSlotId {
+        attribute: name.into(),
+        offset: 0,
+    };
+    if !is_decimal(value) {
+        return Err(invalid());
+    }
+    value.parse().map_err(|_| invalid())
 }
 
-fn parse_object_class(value: &str) -> Result<ObjectClass, &str> {
-    value.try_into().or(Err(value))
+// Characters and percent-encoding of all values are checked by the scanner.
+
+fn check_bytes(_name: &str, _value: &str) -> Result<(), Pkcs11UriError> {
+    Ok(())
 }
 
-fn parse_library_version(value: &str) -> Result<Version, &str> {
-    Ok(if value.contains('.') {
-        let tuple: Vec<&str> = value.splitn(2, '.').collect();
-        let [major, minor]: [&str; 2] = tuple.as_slice().try_into().unwrap();
-        let major = major.parse().or(Err(value))?;
-        let minor = minor.parse().or(Err(value))?;
-        Version { major, minor }
-    } else {
-        let major = value.parse().or(Err(value))?;
-        Version { major, minor: 0 }
-    })
+/// Whether the percent-decoded value is UTF-8, without allocating
+fn is_utf8_when_decoded(value: &str) -> bool {
+    if !value.contains('%') {
+        return true;
+    }
+    let mut buffer = [0u8; 4];
+    let mut len = 0;
+    for byte in percent_encoding::percent_decode_str(value) {
+        buffer[len] = byte;
+        len += 1;
+        match core::str::from_utf8(&buffer[..len]) {
+            Ok(_) => len = 0,
+            // incomplete sequence, wait for more bytes
+            Err(error) if error.error_len().is_none() && len < buffer.len() => {}
+            Err(_) => return false,
+        }
+    }
+    len == 0
+}
+
+fn check_string(name: &str, value: &str) -> Result<(), Pkcs11UriError> {
+    if !is_utf8_when_decoded(value) {
+        return Err(Pkcs11UriError::InvalidUtf8 {
+            attribute: name.into(),
+            offset: 0,
+        });
+    }
+    Ok(())
+}
+
+fn check_non_empty_string(name: &str, value: &str) -> Result<(), Pkcs11UriError> {
+    if value.is_empty() {
+        return Err(Pkcs11UriError::InvalidValue {
+            attribute: name.into(),
+            offset: 0,
+        });
+    }
+    check_string(name, value)
+}
+
+// Decoders are only called on values that passed the corresponding check.
+
+fn decode_string<'a>(_name: &str, value: &'a str) -> Result<Cow<'a, str>, Pkcs11UriError> {
+    Ok(percent_encoding::percent_decode_str(value).decode_utf8_lossy())
+}
+
+fn decode_bytes<'a>(_name: &str, value: &'a str) -> Result<Cow<'a, [u8]>, Pkcs11UriError> {
+    Ok(percent_encoding::percent_decode_str(value).into())
+}
+
+fn parse_object_class(name: &str, value: &str) -> Result<ObjectClass, Pkcs11UriError> {
+    value.try_into().or(Err(Pkcs11UriError::InvalidValue {
+        attribute: name.into(),
+        offset: 0,
+    }))
+}
+
+/// Rejects `type` values RFC 7512 does not define, unless options allow them
+fn check_rfc_object_class(name: &str, value: &str) -> Result<(), Pkcs11UriError> {
+    match ObjectClass::try_from(value) {
+        Ok(class) if class.is_rfc_7512() => Ok(()),
+        _ => Err(Pkcs11UriError::InvalidValue {
+            attribute: name.into(),
+            offset: 0,
+        }),
+    }
+}
+
+fn parse_library_version(name: &str, value: &str) -> Result<Version, Pkcs11UriError> {
+    let invalid = || Pkcs11UriError::InvalidValue {
+        attribute: name.into(),
+        offset: 0,
+    };
+    let (major, minor) = split_once(value, '.').unwrap_or((value, "0"));
+    if !is_decimal(major) || !is_decimal(minor) {
+        return Err(invalid());
+    }
+    let major = major.parse().map_err(|_| invalid())?;
+    let minor = minor.parse().map_err(|_| invalid())?;
+    Ok(Version { major, minor })
+}
+
+fn encode_string(f: &mut fmt::Formatter<'_>, value: &str, set: &'static AsciiSet) -> fmt::Result {
+    write!(f, "{}", percent_encoding::utf8_percent_encode(value, set))
+}
+
+fn encode_secret(
+    f: &mut fmt::Formatter<'_>,
+    value: &SecretPin,
+    set: &'static AsciiSet,
+) -> fmt::Result {
+    encode_string(f, value.expose_secret(), set)
+}
+
+fn encode_bytes(f: &mut fmt::Formatter<'_>, value: &[u8], _set: &'static AsciiSet) -> fmt::Result {
+    // `id` is binary, so every byte is escaped (as in the RFC examples)
+    value.iter().try_for_each(|byte| write!(f, "%{:02X}", byte))
+}
+
+fn encode_display<T: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    value: &T,
+    _set: &'static AsciiSet,
+) -> fmt::Result {
+    write!(f, "{}", value)
+}
+
+fn encode_serial_number(
+    f: &mut fmt::Formatter<'_>,
+    value: &[u8; 16],
+    set: &'static AsciiSet,
+) -> fmt::Result {
+    // strip the blank padding added by `parse_serial_number`
+    let len = value.iter().rposition(|c| *c != b' ').map_or(0, |i| i + 1);
+    write!(
+        f,
+        "{}",
+        percent_encoding::percent_encode(&value[..len], set)
+    )
 }
 
 // character-string serial number of the device. Must be padded with the blank character (' '). Should ''not'' be null-terminated.
@@ -60,14 +283,17 @@ fn parse_library_version(value: &str) -> Result<Version, &str> {
 // In rust-pkcs11, this is `pkcs11::types::padding::BlankPaddedString16`, even though the docs
 // claim it's a UTF-8 string
 
-fn parse_serial_number(value: &str) -> Result<[u8; 16], &str> {
-    let mut characters: Vec<u8> = percent_encoding::percent_decode_str(value).collect();
-    if characters.len() > 16 {
-        Err(value)
-    } else {
-        characters.resize(16, b' ');
-        Ok(characters.try_into().unwrap())
+fn parse_serial_number(name: &str, value: &str) -> Result<[u8; 16], Pkcs11UriError> {
+    let mut serial = [b' '; 16];
+    for (i, character) in percent_encoding::percent_decode_str(value).enumerate() {
+        *serial
+            .get_mut(i)
+            .ok_or_else(|| Pkcs11UriError::SerialTooLong {
+                attribute: name.into(),
+                offset: 0,
+            })? = character;
     }
+    Ok(serial)
 }
 
 // The "library-version" attribute represents the major and minor
@@ -79,97 +305,377 @@ fn parse_serial_number(value: &str) -> Result<[u8; 16], &str> {
 // version number is REQUIRED.  Both "M" and "N" MUST be decimal
 // numbers.
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct Version {
     pub major: u8,
     pub minor: u8,
 }
 
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
 macro_rules! generate {
-    (($Attributes:ident, $delimiter:literal): $($attribute:ident($value:ty, $converter:tt) = $name:literal,)*) => {
+    (($Attributes:ident, $AttributesRef:ident, $component:ident, $delimiter:literal, $set:ident):
+        $($attribute:ident($value:ty, $borrowed:ty, $check:tt, $decode:tt, $encoder:tt) = $name:literal,)*
+    ) => {
 
-        // #[derive(Copy, Clone, Debug, Deserialize, enum_iterator::IntoEnumIterator, PartialEq, Serialize)]
-        #[derive(Clone, Debug, Default, PartialEq)]
+        #[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize))]
         pub struct $Attributes { $(
+            #[cfg_attr(feature = "serde", serde(rename = $name, skip_serializing_if = "Option::is_none"))]
             pub $attribute: Option<$value>,
-        )* }
-
-        impl<'a> TryFrom<&'a str> for $Attributes {
-            type Error = &'a str;
-            fn try_from(input: &'a str) -> std::result::Result<Self, Self::Error> {
-                let mut attributes: $Attributes = Default::default();
-                for component in input.split($delimiter) {
-                    let tuple: Vec<&str> = component.splitn(2, '=').collect();
-                    let [key, value]: [&str; 2] = tuple.as_slice().try_into().unwrap();
-                    match key { $(
-                        $name => {
-                            let value: $value = $converter(value).or(Err(component))?;
-                            if attributes.$attribute.is_some() {
-                                return Err(input);
-                            }
-                            attributes.$attribute = Some(value);
+        )*
+            /// Vendor-specific (`x-` prefixed) attributes, percent-decoded
+            #[cfg_attr(feature = "serde", serde(skip_serializing_if = "BTreeMap::is_empty"))]
+            pub vendor: BTreeMap<String, Vec<u8>>,
+        }
+
+        /// Checked attributes borrowed from the input, decoded when read
+        #[derive(Clone, Copy, Default)]
+        pub struct $AttributesRef<'a> { $(
+            $attribute: Option<&'a str>,
+        )*
+            input: &'a str,
+        }
+
+        /// Raw attribute values, with `pin-value` redacted
+        impl fmt::Debug for $AttributesRef<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct(stringify!($AttributesRef))
+                    $(.field(stringify!($attribute), &self.$attribute.map(|value| redact($name, value))))*
+                    .finish()
+            }
+        }
+
+        impl<'a> TryFrom<&'a str> for $AttributesRef<'a> {
+            type Error = Pkcs11UriError;
+            fn try_from(input: &'a str) -> core::result::Result<Self, Self::Error> {
+                let options = ParseOptions::default();
+                let mut attributes = $AttributesRef { input, ..Default::default() };
+                for item in Scanner::new(input, 0, Component::$component, options) {
+                    let (_, attribute) = item?;
+                    attributes.insert(attribute, &input[..attribute.offset], options)?;
+                }
+                Ok(attributes)
+            }
+        }
+
+        impl<'a> $AttributesRef<'a> {
+            /// Checks and records a scanned attribute; `preceding` is the component before it
+            pub(crate) fn insert(
+                &mut self,
+                attribute: Attribute<'a>,
+                preceding: &str,
+                options: ParseOptions,
+            ) -> core::result::Result<(), Pkcs11UriError> {
+                let Attribute { name, value, offset } = attribute;
+                let duplicate = || Pkcs11UriError::DuplicateAttribute {
+                    attribute: name.into(),
+                    offset,
+                };
+                match name { $(
+                    $name => {
+                        if self.$attribute.is_some() {
+                            return Err(duplicate());
+                        }
+                        $check(name, value)
+                            .map_err(|error| error.shifted(attribute.value_offset()))?;
+                        if $name == "type" && !options.extended_object_classes {
+                            check_rfc_object_class(name, value)
+                                .map_err(|error| error.shifted(attribute.value_offset()))?;
                         }
-                    )*
-                        _ => {
-                            return Err(key);
+                        self.$attribute = Some(value);
+                    }
+                )*
+                    // vendor attributes are rare, so they are not tracked while scanning
+                    _ if is_vendor_attribute_name(name) => {
+                        let seen = preceding
+                            .split($delimiter)
+                            .any(|earlier| earlier.split('=').next() == Some(name));
+                        if seen {
+                            return Err(duplicate());
                         }
                     }
+                    _ => {
+                        return Err(Pkcs11UriError::UnknownAttribute {
+                            attribute: name.into(),
+                            offset,
+                        });
+                    }
                 }
+                Ok(())
+            }
 
+            $(
+                #[doc = concat!("The `", $name, "` attribute")]
+                pub fn $attribute(&self) -> Option<$borrowed> {
+                    self.$attribute.and_then(|value| $decode($name, value).ok())
+                }
+            )*
+
+            /// Vendor-specific (`x-` prefixed) attributes, in input order
+            pub fn vendor(&self) -> impl Iterator<Item = (&'a str, Cow<'a, [u8]>)> {
+                self.input
+                    .split($delimiter)
+                    .filter_map(|component| split_once(component, '='))
+                    .filter(|(key, _)| is_vendor_attribute_name(key))
+                    .map(|(key, value)| (key, percent_encoding::percent_decode_str(value).into()))
+            }
+
+            /// Decodes all attributes into their owned form
+            pub fn to_owned(&self) -> $Attributes {
+                $Attributes { $(
+                    $attribute: self.$attribute().map(<$value>::from),
+                )*
+                    vendor: self
+                        .vendor()
+                        .map(|(key, value)| (key.to_string(), value.into_owned()))
+                        .collect(),
+                }
+            }
+        }
+
+        impl TryFrom<&str> for $Attributes {
+            type Error = Pkcs11UriError;
+            fn try_from(input: &str) -> core::result::Result<Self, Self::Error> {
+                $AttributesRef::try_from(input).map(|attributes| attributes.to_owned())
+            }
+        }
+
+        /// Checked like [`Pkcs11UriBuilder::build`], so that the attributes display as
+        /// a valid URI
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $Attributes {
+            fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                #[derive(Default, serde::Deserialize)]
+                #[serde(default)]
+                struct Unchecked { $(
+                    #[serde(rename = $name)]
+                    $attribute: Option<$value>,
+                )*
+                    vendor: BTreeMap<String, Vec<u8>>,
+                }
+
+                let Unchecked { $($attribute,)* vendor } = Unchecked::deserialize(deserializer)?;
+                let attributes = $Attributes { $($attribute,)* vendor };
+                attributes.check().map_err(serde::de::Error::custom)?;
                 Ok(attributes)
             }
         }
+
+        /// Canonical form: attributes in declaration order followed by vendor
+        /// attributes in name order, percent-encoded as needed
+        impl fmt::Display for $Attributes {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut first = true;
+                $(
+                    if let Some(value) = &self.$attribute {
+                        if !first {
+                            f.write_str($delimiter)?;
+                        }
+                        first = false;
+                        f.write_str(concat!($name, "="))?;
+                        $encoder(f, value, $set)?;
+                    }
+                )*
+                for (name, value) in &self.vendor {
+                    if !first {
+                        f.write_str($delimiter)?;
+                    }
+                    first = false;
+                    write!(f, "{}={}", name, percent_encoding::percent_encode(value, $set))?;
+                }
+                Ok(())
+            }
+        }
     }
 }
 
-generate! { (PathAttributes, ';'):
-    library_description(String, percent_decode_string) = "library-description",
-    library_manufacturer(String, percent_decode_string) = "library-manufacturer",
-    library_version(Version, parse_library_version) = "library-version",
-
-    slot_description(String, percent_decode_string) = "slot-description",
-    slot_id(SlotId, parse_slot_id) = "slot-id",
-    slot_manufacturer(String, percent_decode_string) = "slot-manufacturer",
+generate! { (PathAttributes, PathAttributesRef, Path, ";", PK11_PATH):
+    library_description(String, Cow<'a, str>, check_string, decode_string, encode_string) = "library-description",
+    library_manufacturer(String, Cow<'a, str>, check_string, decode_string, encode_string) = "library-manufacturer",
+    library_version(Version, Version, parse_library_version, parse_library_version, encode_display) = "library-version",
 
-    token_manufacturer(String, percent_decode_string) = "manufacturer",
-    token_model(String, percent_decode_string) = "model",
-    token_label(String, percent_decode_string) = "token",
-    token_serial([u8; 16], parse_serial_number) = "serial",
+    slot_description(String, Cow<'a, str>, check_string, decode_string, encode_string) = "slot-description",
+    slot_id(SlotId, SlotId, parse_slot_id, parse_slot_id, encode_display) = "slot-id",
+    slot_manufacturer(String, Cow<'a, str>, check_string, decode_string, encode_string) = "slot-manufacturer",
 
-    object_class(ObjectClass, parse_object_class) = "type",
-    object_id(Vec<u8>, percent_decode_bytes) = "id",
-    object_label(String, percent_decode_string) = "object",
+    token_manufacturer(String, Cow<'a, str>, check_string, decode_string, encode_string) = "manufacturer",
+    token_model(String, Cow<'a, str>, check_string, decode_string, encode_string) = "model",
+    token_label(String, Cow<'a, str>, check_string, decode_string, encode_string) = "token",
+    token_serial([u8; 16], [u8; 16], parse_serial_number, parse_serial_number, encode_serial_number) = "serial",
 
-    // TODO: vendor attributes
+    object_class(ObjectClass, ObjectClass, parse_object_class, parse_object_class, encode_display) = "type",
+    object_id(Vec<u8>, Cow<'a, [u8]>, check_bytes, decode_bytes, encode_bytes) = "id",
+    object_label(String, Cow<'a, str>, check_string, decode_string, encode_string) = "object",
 }
 
-#[repr(u32)]
-#[derive(Copy, Clone, Debug, PartialEq)]
+// `CKO_*` values from the PKCS #11 v3.0 headers
+const CKO_DATA: CkUlong = 0x0000_0000;
+const CKO_CERTIFICATE: CkUlong = 0x0000_0001;
+const CKO_PUBLIC_KEY: CkUlong = 0x0000_0002;
+const CKO_PRIVATE_KEY: CkUlong = 0x0000_0003;
+const CKO_SECRET_KEY: CkUlong = 0x0000_0004;
+const CKO_HW_FEATURE: CkUlong = 0x0000_0005;
+const CKO_DOMAIN_PARAMETERS: CkUlong = 0x0000_0006;
+const CKO_MECHANISM: CkUlong = 0x0000_0007;
+const CKO_OTP_KEY: CkUlong = 0x0000_0008;
+const CKO_PROFILE: CkUlong = 0x0000_0009;
+const CKO_VENDOR_DEFINED: CkUlong = 0x8000_0000;
+
+/// Object class (`CKO_*`), the `type` path attribute
+///
+/// RFC 7512 only defines names for the first five; the others use the
+/// lowercase, dash-separated `CKO_*` name, and vendor-defined classes are
+/// written in hexadecimal (e.g. `type=0x80000001`).
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum ObjectClass {
-    Certificate = 1,
-    Data = 0,
-    PrivateKey = 3,
-    PublicKey = 2,
-    SecretKey = 4,
-    // OtpKey = 8,
+    Certificate,
+    Data,
+    PrivateKey,
+    PublicKey,
+    SecretKey,
+    HwFeature,
+    DomainParameters,
+    Mechanism,
+    OtpKey,
+    Profile,
+    /// `CKO_VENDOR_DEFINED` or above
+    Vendor(u64),
+}
+
+impl ObjectClass {
+    /// Whether RFC 7512 defines the class's `type` value
+    fn is_rfc_7512(&self) -> bool {
+        use ObjectClass::*;
+        matches!(
+            self,
+            Certificate | Data | PrivateKey | PublicKey | SecretKey
+        )
+    }
+
+    /// Whether a `Vendor` class is `CKO_VENDOR_DEFINED` or above, as parsing requires
+    // `CK_ULONG` is only 32 bits wide on Windows
+    #[allow(clippy::useless_conversion)]
+    fn is_valid(&self) -> bool {
+        match self {
+            ObjectClass::Vendor(class) => *class >= u64::from(CKO_VENDOR_DEFINED),
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for ObjectClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ObjectClass::*;
+        f.write_str(match self {
+            Certificate => "cert",
+            Data => "data",
+            PrivateKey => "private",
+            PublicKey => "public",
+            SecretKey => "secret-key",
+            HwFeature => "hw-feature",
+            DomainParameters => "domain-parameters",
+            Mechanism => "mechanism",
+            OtpKey => "otp-key",
+            Profile => "profile",
+            Vendor(class) => return write!(f, "{:#x}", class),
+        })
+    }
 }
 
-impl<'a> TryFrom<&'a str> for ObjectClass {
-    type Error = &'a str;
-    fn try_from(s: &'a str) -> std::result::Result<Self, Self::Error> {
+impl TryFrom<&str> for ObjectClass {
+    type Error = Pkcs11UriError;
+    fn try_from(s: &str) -> core::result::Result<Self, Self::Error> {
         use ObjectClass::*;
+        let invalid = || Pkcs11UriError::InvalidValue {
+            attribute: "type".into(),
+            offset: 0,
+        };
         Ok(match s {
             "cert" => Certificate,
             "data" => Data,
             "private" => PrivateKey,
             "public" => PublicKey,
             "secret-key" => SecretKey,
-            _ => return Err(s),
+            "hw-feature" => HwFeature,
+            "domain-parameters" => DomainParameters,
+            "mechanism" => Mechanism,
+            "otp-key" => OtpKey,
+            "profile" => Profile,
+            _ => {
+                let hex = s.strip_prefix("0x").ok_or_else(invalid)?;
+                if hex.is_empty() || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(invalid());
+                }
+                let class = u64::from_str_radix(hex, 16).map_err(|_| invalid())?;
+                let class = Vendor(class);
+                if !class.is_valid() {
+                    return Err(invalid());
+                }
+                class
+            }
+        })
+    }
+}
+
+/// Conversion to `CK_OBJECT_CLASS`
+impl TryFrom<ObjectClass> for CkUlong {
+    /// The vendor-defined class, if `CK_ULONG` is too narrow for it
+    type Error = u64;
+    fn try_from(class: ObjectClass) -> core::result::Result<Self, Self::Error> {
+        use ObjectClass::*;
+        Ok(match class {
+            Certificate => CKO_CERTIFICATE,
+            Data => CKO_DATA,
+            PrivateKey => CKO_PRIVATE_KEY,
+            PublicKey => CKO_PUBLIC_KEY,
+            SecretKey => CKO_SECRET_KEY,
+            HwFeature => CKO_HW_FEATURE,
+            DomainParameters => CKO_DOMAIN_PARAMETERS,
+            Mechanism => CKO_MECHANISM,
+            OtpKey => CKO_OTP_KEY,
+            Profile => CKO_PROFILE,
+            // `CK_ULONG` is only 32 bits wide on Windows
+            #[allow(clippy::useless_conversion)]
+            Vendor(class) => CkUlong::try_from(class).map_err(|_| class)?,
         })
     }
 }
 
-generate! { (QueryAttributes, '&'):
+/// Conversion from `CK_OBJECT_CLASS`
+impl TryFrom<CkUlong> for ObjectClass {
+    /// The value, if it is neither a known nor a vendor-defined class
+    type Error = CkUlong;
+    // `CK_ULONG` is only 32 bits wide on Windows
+    #[allow(clippy::useless_conversion)]
+    fn try_from(class: CkUlong) -> core::result::Result<Self, Self::Error> {
+        use ObjectClass::*;
+        Ok(match class {
+            CKO_CERTIFICATE => Certificate,
+            CKO_DATA => Data,
+            CKO_PRIVATE_KEY => PrivateKey,
+            CKO_PUBLIC_KEY => PublicKey,
+            CKO_SECRET_KEY => SecretKey,
+            CKO_HW_FEATURE => HwFeature,
+            CKO_DOMAIN_PARAMETERS => DomainParameters,
+            CKO_MECHANISM => Mechanism,
+            CKO_OTP_KEY => OtpKey,
+            CKO_PROFILE => Profile,
+            class if class >= CKO_VENDOR_DEFINED => Vendor(class.into()),
+            class => return Err(class),
+        })
+    }
+}
+
+generate! { (QueryAttributes, QueryAttributesRef, Query, "&", PK11_QUERY):
 
     // should these be merged, and expect at most one of them?
     // NOTE: "the "pin-source" attribute value format and interpretation is left to be implementation specific"
@@ -177,269 +683,161 @@ generate! { (QueryAttributes, '&'):
     // - either a file/https URI, or
     // - a specification how to call an external application (e.g., `|/usr/bin/echo $PIN` perhaps?)
     // I think it would be useful to support environment variables directly (e.g., `env:PIN`)
-    pin_source(String, percent_decode_string) = "pin-source",
-    pin_value(String, percent_decode_string) = "pin-value",
+    pin_source(String, Cow<'a, str>, check_string, decode_string, encode_string) = "pin-source",
+    pin_value(SecretPin, Cow<'a, str>, check_string, decode_string, encode_secret) = "pin-value",
 
     // should these be merged, and expect at most one of them?
-    module_name(String, percent_decode_string) = "module-name",
-    module_path(String, percent_decode_string) = "module-path",
-
-    // TODO: vendor attributes
+    module_name(String, Cow<'a, str>, check_non_empty_string, decode_string, encode_string) = "module-name",
+    module_path(String, Cow<'a, str>, check_non_empty_string, decode_string, encode_string) = "module-path",
 }
 
 /// Parsed [RFC 7512](https://tools.ietf.org/html/rfc7512) PKCS #11 URI
-#[derive(Clone, Debug, PartialEq)]
+///
+/// Comparison, ordering and hashing only consider the decoded attributes, so URIs
+/// that differ in attribute order, percent-encoding case or unnecessary escaping
+/// are equal (see section 2.5 of the RFC). The `Display` form is the normalized one.
+#[derive(Clone, Debug)]
 pub struct Pkcs11Uri {
     pub path_attributes: PathAttributes,
     pub query_attributes: QueryAttributes,
-    raw_uri: String,
 }
 
 impl Pkcs11Uri {
-    /// TryFrom as inherent method
-    pub fn try_from(uri_str: &str) -> anyhow::Result<Self> {
-        // 0. strip whitespace
-        let uri_string: String = uri_str.chars().filter(|c| !c.is_whitespace()).collect();
-
-        // 1. uriparse from string, check validity
-        let uri = uriparse::URIReference::try_from(uri_string.as_str())?;
-        // dbg!(&uri);
-
-        if uri.scheme() != Some(&uriparse::Scheme::PKCS11) {
-            return Err(anyhow!("URI should have PKCS11 scheme"));
-        }
-        if uri.authority().is_some() {
-            return Err(anyhow!("URI should not have an authority"));
-        }
-
-        if uri.path().segments().len() != 1 {
-            return Err(anyhow!("URI should have exactly one segment"));
-        }
-
-        // 2. parse Path Attributes
-        let segment = uri.path().segments()[0].as_str();
-        debug!("segment: {}", segment);
-        let path_attributes = PathAttributes::try_from(segment).unwrap();
-
-        // 3. parse Query Attributes
-        let query = uri.query().map(|query| query.as_str()).unwrap_or("");
-        debug!("query: {}", query);
-        let query_attributes = QueryAttributes::try_from(query).unwrap();
-
-        // 4. wrap up
-        let parsed_uri = Pkcs11Uri {
-            path_attributes,
-            query_attributes,
-            raw_uri: uri_string,
-        };
+    fn attributes(&self) -> (&PathAttributes, &QueryAttributes) {
+        (&self.path_attributes, &self.query_attributes)
+    }
+}
 
-        Ok(parsed_uri)
+impl PartialEq for Pkcs11Uri {
+    fn eq(&self, other: &Self) -> bool {
+        self.attributes() == other.attributes()
     }
 }
 
-impl<'a> TryFrom<&'a str> for Pkcs11Uri {
-    type Error = anyhow::Error;
+impl Eq for Pkcs11Uri {}
 
-    fn try_from(uri_str: &str) -> std::result::Result<Self, Self::Error> {
-        Self::try_from(uri_str)
+impl core::hash::Hash for Pkcs11Uri {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.attributes().hash(state)
     }
 }
 
-pub fn split_once(s: &str, delimiter: char) -> Option<(&str, &str)> {
-    let i = s.find(delimiter)?;
-    Some((&s[..i], &s[i + 1..]))
+impl PartialOrd for Pkcs11Uri {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
-impl Pkcs11Uri {
-    fn matches_slot(&self, ctx: &pkcs11::Ctx, slot_id: pkcs11::types::CK_SLOT_ID) -> bool {
-        // slot_id, slot_description, slot_manufacturer
-
-        if self.path_attributes.slot_id == Some(slot_id) {
-            return false;
-        }
-        let info = ctx.get_slot_info(slot_id).unwrap();
-        trace!("{:?}", info);
-
-        if let Some(slot_description) = &self.path_attributes.slot_description {
-            if slot_description != String::from(info.slotDescription).as_str() {
-                return false;
-            }
-        }
-        if let Some(slot_manufacturer) = &self.path_attributes.slot_manufacturer {
-            if slot_manufacturer != String::from(info.manufacturerID).as_str() {
-                return false;
-            }
-        }
-        true
+impl Ord for Pkcs11Uri {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.attributes().cmp(&other.attributes())
     }
+}
 
-    fn matches_token(&self, ctx: &pkcs11::Ctx, slot_id: pkcs11::types::CK_SLOT_ID) -> bool {
-        // slot_id, token_manufacturer, token_model, token_label
-
-        if self.path_attributes.slot_id == Some(slot_id) {
-            return false;
-        }
-
-        let info = ctx.get_token_info(slot_id).unwrap();
-        trace!("{:?}", info);
-
-        if let Some(token_manufacturer) = &self.path_attributes.token_manufacturer {
-            if token_manufacturer != String::from(info.manufacturerID).as_str() {
-                trace!("failed token_manufacturer check");
-                return false;
-            }
-        }
-        if let Some(token_model) = &self.path_attributes.token_model {
-            if token_model != String::from(info.model).as_str() {
-                trace!("failed token_model check");
-                return false;
-            }
+/// Byte ranges of line folds: a line break together with the blanks around it
+fn line_folds(input: &str) -> Vec<Range<usize>> {
+    let bytes = input.as_bytes();
+    let is_blank = |byte: u8| byte == b' ' || byte == b'\t';
+    let mut folds: Vec<Range<usize>> = Vec::new();
+    for (i, _) in input.match_indices('\n') {
+        let floor = folds.last().map_or(0, |fold| fold.end);
+        let mut start = i;
+        if start > floor && bytes[start - 1] == b'\r' {
+            start -= 1;
         }
-        if let Some(token_label) = &self.path_attributes.token_label {
-            if token_label != String::from(info.label).as_str() {
-                trace!("failed token_label check");
-                return false;
-            }
+        while start > floor && is_blank(bytes[start - 1]) {
+            start -= 1;
         }
-        if let Some(token_serial) = &self.path_attributes.token_serial {
-            if token_serial != &info.serialNumber.0 {
-                trace!("failed token_serial check");
-                return false;
-            }
+        let mut end = i + 1;
+        while end < bytes.len() && is_blank(bytes[end]) {
+            end += 1;
         }
-
-        true
-    }
-
-    pub fn context(&self) -> Context {
-        Context::new_and_initialize(self.query_attributes.module_path.as_ref().unwrap()).unwrap()
+        folds.push(start..end);
     }
+    folds
+}
 
-    pub fn identify_slots(&self) -> anyhow::Result<Vec<SlotId>> {
-        let ctx = self.context();
-
-        let slots: Vec<SlotId> = ctx
-            .get_slot_list(true)
-            .unwrap()
-            .iter()
-            .copied()
-            .filter(|slot| self.matches_slot(&ctx, *slot))
-            .collect();
-
-        Ok(slots)
+/// Maps an offset into the unfolded URI back to the original input
+fn original_offset(folds: &[Range<usize>], unfolded_offset: usize) -> usize {
+    let mut offset = unfolded_offset;
+    for fold in folds {
+        if fold.start > offset {
+            break;
+        }
+        offset += fold.len();
     }
+    offset
+}
 
-    pub fn identify_tokens(&self) -> anyhow::Result<Vec<SlotId>> {
-        let ctx = self.context();
-
-        let slots: Vec<SlotId> = ctx
-            .get_slot_list(true)
-            .unwrap()
-            .iter()
-            .copied()
-            .filter(|slot| self.matches_slot(&ctx, *slot))
-            .filter(|slot| self.matches_token(&ctx, *slot))
-            .collect();
-
-        Ok(slots)
+impl Pkcs11Uri {
+    /// TryFrom as inherent method, with [`ParseOptions::default`]
+    pub fn try_from(uri_str: &str) -> Result<Self, Pkcs11UriError> {
+        Self::parse_with(uri_str, ParseOptions::default())
     }
 
-    pub fn identify_object(&self) -> anyhow::Result<(Context, SessionHandle, ObjectHandle)> {
-        let ctx = self.context();
-
-        // 1. find the slot
-        let slots: Vec<SlotId> = ctx
-            .get_slot_list(true)
-            .unwrap()
-            .iter()
-            .copied()
-            .filter(|slot| self.matches_slot(&ctx, *slot))
-            .filter(|slot| self.matches_token(&ctx, *slot))
-            .collect();
-
-        debug!("slots: {:?}", slots);
-
-        if slots.is_empty() {
-            return Err(anyhow!("No slots found for URI `{}`", &self.raw_uri));
-        }
-        if slots.len() > 1 {
-            return Err(anyhow!("Not implemented for multiple applicable slots"));
+    /// Parses a URI following `options`
+    ///
+    /// Error offsets refer to `uri_str`, including any line folding.
+    pub fn parse_with(uri_str: &str, options: ParseOptions) -> Result<Self, Pkcs11UriError> {
+        if !options.line_folding || !uri_str.contains('\n') {
+            return Pkcs11UriRef::parse_with(uri_str, options).map(|uri| uri.to_owned());
         }
 
-        let slot = slots[0];
-
-        // 2. create a logged-in session with the slot
-
-        let flags = pkcs11::types::CKF_SERIAL_SESSION | pkcs11::types::CKF_RW_SESSION;
-        let session = ctx
-            .open_session(
-                slot, flags, /*application: */ None, /*notify: */ None,
-            )
-            .unwrap();
-
-        if let Some(pin) = self.query_attributes.pin_value.as_deref() {
-            trace!("{:?}", pin);
-            ctx.login(session, pkcs11::types::CKU_USER, Some(pin))
-                .unwrap();
-        } else if let Some(source) = self.query_attributes.pin_source.as_deref() {
-            if let Some((scheme, content)) = split_once(source, ':') {
-                match scheme {
-                    "env" => {
-                        let pin = std::env::var(content).unwrap();
-                        trace!("{:?}", pin);
-                        ctx.login(session, pkcs11::types::CKU_USER, Some(&pin))
-                            .unwrap();
-                    }
-                    "file" => {
-                        let pin = String::from_utf8_lossy(&std::fs::read(content).unwrap())
-                            .trim()
-                            .to_string();
-                        trace!("{:?}", pin);
-                        ctx.login(session, pkcs11::types::CKU_USER, Some(pin.as_str()))
-                            .unwrap();
-                    }
-                    _ => {}
-                }
-            }
-        } else {
-            // no PIN = no login
-            // ctx.login(session, pkcs11::types::CKU_USER, None).unwrap();
-        }
-
-        // 3. find the object
-        // object_class: Option<ObjectClass>
-        // object_id: Option<Vec<u8>>
-        // object_label: Option<String>
-
-        type Attribute = pkcs11::types::CK_ATTRIBUTE;
-        let mut template = Vec::<Attribute>::new();
-        if let Some(object_label) = &self.path_attributes.object_label {
-            template.push(Attribute::new(pkcs11::types::CKA_LABEL).with_string(object_label));
-        }
-        if let Some(object_id) = &self.path_attributes.object_id {
-            template.push(Attribute::new(pkcs11::types::CKA_ID).with_bytes(object_id.as_ref()));
-        }
-        if let Some(object_class) = &self.path_attributes.object_class {
-            let raw_object_class = *object_class as u8 as _;
-            template
-                .push(Attribute::new(pkcs11::types::CKA_CLASS).with_ck_ulong(&raw_object_class));
+        // 0. unfold lines
+        let folds = line_folds(uri_str);
+        let mut uri_string = Zeroizing::new(String::with_capacity(uri_str.len()));
+        let mut position = 0;
+        for fold in &folds {
+            uri_string.push_str(&uri_str[position..fold.start]);
+            position = fold.end;
         }
+        uri_string.push_str(&uri_str[position..]);
+
+        // 1. check the URI against the grammar
+        let uri = Pkcs11UriRef::parse_with(&uri_string, options).map_err(|error| {
+            let offset = original_offset(&folds, error.offset());
+            error.with_offset(offset)
+        })?;
+        debug!(
+            "path: {:?}, query: {:?}",
+            uri.path_attributes, uri.query_attributes
+        );
+
+        // 2. decode the attributes
+        Ok(uri.to_owned())
+    }
+}
 
-        ctx.find_objects_init(session, &template).unwrap();
-        // ctx.find_objects_init(session, &[]).unwrap();
-        let objects = ctx.find_objects(session, 10).unwrap();
-        ctx.find_objects_final(session).unwrap();
+impl TryFrom<&str> for Pkcs11Uri {
+    type Error = Pkcs11UriError;
 
-        debug!("objects: {:?}", objects);
+    fn try_from(uri_str: &str) -> core::result::Result<Self, Self::Error> {
+        Self::try_from(uri_str)
+    }
+}
 
-        if objects.is_empty() {
-            return Err(anyhow!("No objects found for URI `{}`", &self.raw_uri));
-        }
-        if objects.len() > 1 {
-            return Err(anyhow!("Not implemented for multiple applicable objects"));
+/// Canonical RFC 7512 form, which parses back into an equal set of attributes
+impl fmt::Display for Pkcs11Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pkcs11:{}", self.path_attributes)?;
+        let query = Zeroizing::new(self.query_attributes.to_string());
+        if !query.is_empty() {
+            write!(f, "?{}", query.as_str())?;
         }
+        Ok(())
+    }
+}
 
-        let object = objects[0];
-        Ok((ctx, session, object))
+/// Hides secret values from `Debug` output
+fn redact<'a>(name: &str, value: &'a str) -> &'a str {
+    if name == "pin-value" {
+        SecretPin::PLACEHOLDER
+    } else {
+        value
     }
 }
+
+pub fn split_once(s: &str, delimiter: char) -> Option<(&str, &str)> {
+    let i = s.find(delimiter)?;
+    Some((&s[..i], &s[i + 1..]))
+}
@@ -0,0 +1,284 @@
+//! Resolving `module-name` to a library path, using the p11-kit module configuration
+//!
+//! p11-kit registers each module in a `<name>.module` file, looked up in the user,
+//! system and package configuration directories, in that order of precedence. Entries
+//! of a more specific file override those of the same name in a less specific one.
+//! Only the `module` entry is used here; a relative `module` is looked up in the
+//! library directories.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use log::{debug, trace};
+
+/// Where a module path was found
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ModuleSource {
+    /// The `module` entry of this p11-kit configuration file
+    Config(PathBuf),
+    /// Found by file name in this library directory
+    LibraryDir(PathBuf),
+}
+
+/// A module name resolved to the library to load
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ResolvedModule {
+    pub name: String,
+    pub path: PathBuf,
+    pub source: ModuleSource,
+}
+
+/// Maps `module-name` values to library paths
+///
+/// [`ModuleResolver::default`] uses the p11-kit directories of the system; for tests,
+/// start from [`ModuleResolver::new`] and add directories.
+///
+/// ```no_run
+/// use pkcs11_uri::ModuleResolver;
+///
+/// let module = ModuleResolver::default().resolve("softhsm2").unwrap();
+/// println!("{} (from {:?})", module.path.display(), module.source);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ModuleResolver {
+    config_dirs: Vec<PathBuf>,
+    library_dirs: Vec<PathBuf>,
+    search_modules: Vec<PathBuf>,
+}
+
+impl ModuleResolver {
+    /// A resolver without any directories
+    pub fn new() -> Self {
+        ModuleResolver {
+            config_dirs: Vec::new(),
+            library_dirs: Vec::new(),
+            search_modules: Vec::new(),
+        }
+    }
+
+    /// The p11-kit configuration and standard library directories of this system
+    pub fn system() -> Self {
+        let mut resolver = ModuleResolver::new();
+        if let Some(home) = std::env::var_os("HOME") {
+            resolver = resolver.config_dir(Path::new(&home).join(".config/pkcs11/modules"));
+        }
+        resolver = resolver
+            .config_dir("/etc/pkcs11/modules")
+            .config_dir("/usr/share/p11-kit/modules");
+
+        let multiarch = multiarch_tuple().map(|tuple| Path::new("/usr/lib").join(tuple));
+        let lib_dirs: Vec<PathBuf> = multiarch
+            .into_iter()
+            .chain(["/usr/lib64", "/usr/lib"].iter().map(PathBuf::from))
+            .collect();
+        for dir in &lib_dirs {
+            resolver = resolver.library_dir(dir.join("pkcs11"));
+        }
+        for dir in lib_dirs {
+            resolver = resolver.library_dir(dir);
+        }
+        resolver.library_dir("/usr/local/lib")
+    }
+
+    /// Adds a directory of `*.module` files, with lower precedence than those added before
+    pub fn config_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config_dirs.push(dir.into());
+        self
+    }
+
+    /// Adds a directory to search for libraries, after those added before
+    pub fn library_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.library_dirs.push(dir.into());
+        self
+    }
+
+    /// Adds a module to search for URIs without `module-path` or `module-name`
+    ///
+    /// Once any is added, only these are searched instead of the registered modules.
+    pub fn search_module(mut self, path: impl Into<PathBuf>) -> Self {
+        self.search_modules.push(path.into());
+        self
+    }
+
+    /// Libraries to search for URIs without `module-path` or `module-name`
+    pub fn search_modules(&self) -> Vec<PathBuf> {
+        if !self.search_modules.is_empty() {
+            return self.search_modules.clone();
+        }
+        self.registered_modules()
+            .into_iter()
+            .map(|module| module.path)
+            .collect()
+    }
+
+    /// All modules registered in the configuration directories, ordered by name
+    pub fn registered_modules(&self) -> Vec<ResolvedModule> {
+        // name -> (module, file that set it)
+        let mut registered: BTreeMap<String, Option<(String, PathBuf)>> = BTreeMap::new();
+        for dir in self.config_dirs.iter().rev() {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            let mut files: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|extension| extension == "module")
+                })
+                .collect();
+            files.sort();
+            for file in files {
+                let name = match file.file_stem().and_then(|stem| stem.to_str()) {
+                    Some(name) => name.to_string(),
+                    None => continue,
+                };
+                let content = match fs::read_to_string(&file) {
+                    Ok(content) => content,
+                    Err(err) => {
+                        debug!("skipping {}: {}", file.display(), err);
+                        continue;
+                    }
+                };
+                let entry = registered.entry(name).or_insert(None);
+                if let Some(module) = config_value(&content, "module") {
+                    *entry = Some((module.to_string(), file));
+                }
+            }
+        }
+
+        registered
+            .into_iter()
+            .filter_map(|(name, module)| {
+                let (module, file) = module?;
+                Some(ResolvedModule {
+                    name,
+                    path: self.library_path(&module),
+                    source: ModuleSource::Config(file),
+                })
+            })
+            .collect()
+    }
+
+    /// Resolves a `module-name`, first by p11-kit configuration, then by library file name
+    ///
+    /// A registered module matches by configuration name or by the file name of its
+    /// library, with or without `lib` prefix and suffix (`softhsm2` for `libsofthsm2.so`).
+    /// Names are not paths, so names with separators or `..` are rejected; URIs name
+    /// libraries elsewhere with `module-path`.
+    pub fn resolve(&self, name: &str) -> anyhow::Result<ResolvedModule> {
+        let is_path =
+            name.contains(&['/', '\\'][..]) || name.contains("..") || Path::new(name).is_absolute();
+        if is_path {
+            return Err(anyhow!("Module name `{}` is a path", name));
+        }
+        let registered = self.registered_modules();
+        let module = registered
+            .iter()
+            .find(|module| module.name == name)
+            .or_else(|| {
+                registered
+                    .iter()
+                    .find(|module| library_name_matches(&module.path, name))
+            });
+        if let Some(module) = module {
+            debug!("resolved module `{}`: {:?}", name, module);
+            return Ok(ResolvedModule {
+                name: name.to_string(),
+                ..module.clone()
+            });
+        }
+
+        for dir in &self.library_dirs {
+            for file_name in library_file_names(name).iter() {
+                let path = dir.join(file_name);
+                trace!("trying {}", path.display());
+                if path.is_file() {
+                    return Ok(ResolvedModule {
+                        name: name.to_string(),
+                        path,
+                        source: ModuleSource::LibraryDir(dir.clone()),
+                    });
+                }
+            }
+        }
+
+        Err(anyhow!("Module `{}` not found", name))
+    }
+
+    /// Relative `module` entries are looked up in the library directories, or else
+    /// left to the dynamic loader
+    fn library_path(&self, module: &str) -> PathBuf {
+        let path = PathBuf::from(module);
+        if path.is_absolute() {
+            return path;
+        }
+        self.library_dirs
+            .iter()
+            .map(|dir| dir.join(&path))
+            .find(|path| path.is_file())
+            .unwrap_or(path)
+    }
+}
+
+impl Default for ModuleResolver {
+    fn default() -> Self {
+        ModuleResolver::system()
+    }
+}
+
+/// The Debian multiarch tuple of the target, which names its library directories
+fn multiarch_tuple() -> Option<&'static str> {
+    if !cfg!(all(target_os = "linux", target_env = "gnu")) {
+        return None;
+    }
+    Some(if cfg!(target_arch = "x86_64") {
+        "x86_64-linux-gnu"
+    } else if cfg!(target_arch = "x86") {
+        "i386-linux-gnu"
+    } else if cfg!(target_arch = "aarch64") {
+        "aarch64-linux-gnu"
+    } else if cfg!(all(target_arch = "arm", target_abi = "eabihf")) {
+        "arm-linux-gnueabihf"
+    } else if cfg!(target_arch = "arm") {
+        "arm-linux-gnueabi"
+    } else if cfg!(all(target_arch = "powerpc64", target_endian = "little")) {
+        "powerpc64le-linux-gnu"
+    } else if cfg!(target_arch = "riscv64") {
+        "riscv64-linux-gnu"
+    } else if cfg!(target_arch = "s390x") {
+        "s390x-linux-gnu"
+    } else {
+        return None;
+    })
+}
+
+/// The value of the first `key: value` line; `#` starts a comment
+fn config_value<'a>(content: &'a str, key: &str) -> Option<&'a str> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter_map(|line| crate::split_once(line, ':'))
+        .find(|(name, _)| name.trim() == key)
+        .map(|(_, value)| value.trim())
+}
+
+fn library_file_names(name: &str) -> [String; 2] {
+    use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+    [
+        format!("{}{}", name, DLL_SUFFIX),
+        format!("{}{}{}", DLL_PREFIX, name, DLL_SUFFIX),
+    ]
+}
+
+fn library_name_matches(path: &Path, name: &str) -> bool {
+    let file_name = match path.file_name().and_then(|file_name| file_name.to_str()) {
+        Some(file_name) => file_name,
+        None => return false,
+    };
+    library_file_names(name)
+        .iter()
+        .any(|candidate| candidate == file_name)
+}
@@ -0,0 +1,96 @@
+//! Iterating over the results of an object search
+
+use std::collections::VecDeque;
+
+use anyhow::anyhow;
+use log::{debug, trace};
+use pkcs11::types::CK_ATTRIBUTE;
+
+use crate::{ModuleSlot, ObjectHandle, Session};
+
+/// Handles fetched per `C_FindObjects` call
+const BATCH_SIZE: pkcs11::types::CK_ULONG = 64;
+
+/// All objects matching a search, fetched in batches as the iterator advances
+///
+/// The search stays active on the session until it is exhausted, so the session
+/// should only be used for other operations after [`into_session`](Self::into_session).
+/// Dropping the iterator closes the session, which also ends the search.
+#[derive(Debug)]
+pub struct Objects {
+    session: Session,
+    batch: VecDeque<ObjectHandle>,
+    active: bool,
+}
+
+impl Objects {
+    /// Starts a search for objects matching `template`
+    pub(crate) fn find(session: Session, template: &[CK_ATTRIBUTE]) -> anyhow::Result<Self> {
+        session
+            .module()
+            .find_objects_init(session.handle(), template)
+            .map_err(|err| anyhow!("Failed to start object search: {}", err))?;
+        Ok(Objects {
+            session,
+            batch: VecDeque::new(),
+            active: true,
+        })
+    }
+
+    /// The session the objects belong to
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// The slot the search is on
+    pub fn slot(&self) -> ModuleSlot {
+        ModuleSlot {
+            module_path: self.session.module().path().to_path_buf(),
+            slot_id: self.session.slot_id(),
+        }
+    }
+
+    /// Ends the search, returning the session to use the objects with
+    pub fn into_session(mut self) -> Session {
+        self.finish();
+        self.session
+    }
+
+    fn finish(&mut self) {
+        if !self.active {
+            return;
+        }
+        self.active = false;
+        if let Err(err) = self
+            .session
+            .module()
+            .find_objects_final(self.session.handle())
+        {
+            debug!("Failed to end object search: {}", err);
+        }
+    }
+}
+
+impl Iterator for Objects {
+    type Item = anyhow::Result<ObjectHandle>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.batch.is_empty() && self.active {
+            let module = self.session.module();
+            match module.find_objects(self.session.handle(), BATCH_SIZE) {
+                Ok(batch) => {
+                    trace!("objects: {:?}", batch);
+                    if batch.is_empty() {
+                        self.finish();
+                    }
+                    self.batch.extend(batch);
+                }
+                Err(err) => {
+                    self.finish();
+                    return Some(Err(anyhow!("Failed to find objects: {}", err)));
+                }
+            }
+        }
+        self.batch.pop_front().map(Ok)
+    }
+}
@@ -0,0 +1,255 @@
+//! Single-pass scanner for the RFC 7512 grammar
+//!
+//! Splits the path and query components into `name=value` attributes, checking on the
+//! way that every value consists of `pk11-pchar` / `pk11-qchar` characters and
+//! well-formed escapes. Error offsets are byte offsets into the scanned string.
+
+use crate::{is_pk11_char, Pkcs11UriError, PK11_PATH_RES_AVAIL, PK11_QUERY_RES_AVAIL};
+
+/// How closely input must follow RFC 7512
+///
+/// [`strict`](ParseOptions::strict) accepts exactly the RFC grammar and object classes,
+/// for linting; [`lenient`](ParseOptions::lenient), the default, accepts the sloppier
+/// URIs people write by hand. In both modes, characters outside `pk11-pchar` /
+/// `pk11-qchar` must be escaped, `library-version` must be exactly `M` or `M.N`, and
+/// whitespace other than line folding is an error rather than being removed from
+/// attribute values.
+///
+/// ```
+/// use pkcs11_uri::{ParseOptions, Pkcs11Uri};
+///
+/// let uri = "pkcs11:token=my-ca;\n    object=my-key;";
+/// assert!(Pkcs11Uri::parse_with(uri, ParseOptions::strict()).is_err());
+/// assert!(Pkcs11Uri::parse_with(uri, ParseOptions::lenient()).is_ok());
+/// assert!(Pkcs11Uri::try_from("pkcs11:object=my key").is_err());
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ParseOptions {
+    /// Remove line breaks together with the blanks around them
+    ///
+    /// Only applies to [`Pkcs11Uri`](crate::Pkcs11Uri), which owns its input.
+    pub line_folding: bool,
+    /// Accept lowercase hexadecimal digits in escapes (`%2f`)
+    pub lowercase_escapes: bool,
+    /// Accept a `;` or `&` after the last attribute of a component
+    pub trailing_delimiter: bool,
+    /// Accept `type` values beyond the five RFC 7512 defines, such as `otp-key` or
+    /// vendor-defined classes
+    pub extended_object_classes: bool,
+}
+
+impl ParseOptions {
+    /// The RFC 7512 grammar with uppercase escapes, as RFC 3986 recommends
+    pub const fn strict() -> Self {
+        ParseOptions {
+            line_folding: false,
+            lowercase_escapes: false,
+            trailing_delimiter: false,
+            extended_object_classes: false,
+        }
+    }
+
+    /// Line folding, either case in escapes, trailing delimiters and all object classes
+    pub const fn lenient() -> Self {
+        ParseOptions {
+            line_folding: true,
+            lowercase_escapes: true,
+            trailing_delimiter: true,
+            extended_object_classes: true,
+        }
+    }
+
+    fn is_hex_digit(&self, byte: u8) -> bool {
+        match byte {
+            b'0'..=b'9' | b'A'..=b'F' => true,
+            b'a'..=b'f' => self.lowercase_escapes,
+            _ => false,
+        }
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions::lenient()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Component {
+    Path,
+    Query,
+}
+
+impl Component {
+    fn delimiter(self) -> u8 {
+        match self {
+            Component::Path => b';',
+            Component::Query => b'&',
+        }
+    }
+
+    fn res_avail(self) -> &'static [u8] {
+        match self {
+            Component::Path => PK11_PATH_RES_AVAIL,
+            Component::Query => PK11_QUERY_RES_AVAIL,
+        }
+    }
+}
+
+/// A `name=value` pair, with its value still percent-encoded
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Attribute<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+    /// Offset of the name
+    pub offset: usize,
+}
+
+impl Attribute<'_> {
+    pub fn value_offset(&self) -> usize {
+        self.offset + self.name.len() + 1
+    }
+}
+
+pub(crate) struct Scanner<'a> {
+    input: &'a str,
+    options: ParseOptions,
+    position: usize,
+    component: Component,
+    /// Whether a `?` ends the path component, as opposed to being an invalid character
+    query_follows: bool,
+    query_start: Option<usize>,
+    /// Whether the last byte was a delimiter, so that an attribute must follow
+    after_delimiter: bool,
+}
+
+impl<'a> Scanner<'a> {
+    /// Scans a single component starting at `position`
+    pub fn new(
+        input: &'a str,
+        position: usize,
+        component: Component,
+        options: ParseOptions,
+    ) -> Self {
+        Scanner {
+            input,
+            options,
+            position,
+            component,
+            query_follows: false,
+            query_start: None,
+            after_delimiter: false,
+        }
+    }
+
+    /// Scans a path component starting at `position`, optionally followed by a query
+    pub fn uri(input: &'a str, position: usize, options: ParseOptions) -> Self {
+        Scanner {
+            query_follows: true,
+            ..Scanner::new(input, position, Component::Path, options)
+        }
+    }
+
+    /// Offset of the query component, once the scanner has reached it
+    pub fn query_start(&self) -> Option<usize> {
+        self.query_start
+    }
+
+    fn ends_path(&self, byte: u8) -> bool {
+        self.query_follows && self.component == Component::Path && byte == b'?'
+    }
+
+    fn start_query(&mut self, position: usize) {
+        self.component = Component::Query;
+        self.query_start = Some(position);
+        self.position = position;
+        self.after_delimiter = false;
+    }
+
+    fn scan(&mut self) -> Option<Result<(Component, Attribute<'a>), Pkcs11UriError>> {
+        let bytes = self.input.as_bytes();
+        let start = self.position;
+
+        // empty component, or end of one
+        if !self.after_delimiter || self.options.trailing_delimiter {
+            if start == bytes.len() {
+                return None;
+            }
+            if self.ends_path(bytes[start]) {
+                self.start_query(start + 1);
+                return self.scan();
+            }
+        }
+
+        let component = self.component;
+        let delimiter = component.delimiter();
+        let res_avail = component.res_avail();
+
+        // attribute names are `1*(ALPHA / DIGIT / "-" / "_")`, all others are unknown
+        let is_name_char = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_';
+        let mut i = start;
+        while i < bytes.len() && is_name_char(bytes[i]) {
+            i += 1;
+        }
+        if i == start || i == bytes.len() || bytes[i] != b'=' {
+            return Some(Err(Pkcs11UriError::InvalidSyntax { offset: start }));
+        }
+        let name = &self.input[start..i];
+
+        let value_start = i + 1;
+        let mut i = value_start;
+        while i < bytes.len() && bytes[i] != delimiter && !self.ends_path(bytes[i]) {
+            match bytes[i] {
+                b'%' => {
+                    let escape = bytes.get(i + 1..i + 3);
+                    let options = &self.options;
+                    if !escape.is_some_and(|hex| hex.iter().all(|c| options.is_hex_digit(*c))) {
+                        return Some(Err(Pkcs11UriError::InvalidPercentEncoding {
+                            attribute: name.into(),
+                            offset: i,
+                        }));
+                    }
+                    i += 3;
+                }
+                byte if is_pk11_char(byte, res_avail) => i += 1,
+                _ => {
+                    return Some(Err(Pkcs11UriError::InvalidCharacter {
+                        attribute: name.into(),
+                        offset: i,
+                    }));
+                }
+            }
+        }
+        let attribute = Attribute {
+            name,
+            value: &self.input[value_start..i],
+            offset: start,
+        };
+
+        if i == bytes.len() {
+            self.position = i;
+            self.after_delimiter = false;
+        } else if bytes[i] == delimiter {
+            self.position = i + 1;
+            self.after_delimiter = true;
+        } else {
+            self.start_query(i + 1);
+        }
+        Some(Ok((component, attribute)))
+    }
+}
+
+impl<'a> Iterator for Scanner<'a> {
+    type Item = Result<(Component, Attribute<'a>), Pkcs11UriError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.scan();
+        if let Some(Err(_)) = item {
+            // stop after the first error
+            self.position = self.input.len();
+            self.after_delimiter = false;
+        }
+        item
+    }
+}
@@ -0,0 +1,267 @@
+//! Reading PINs from `pin-source`
+//!
+//! RFC 7512 leaves the interpretation of `pin-source` to the application. Here it is a
+//! URI whose scheme selects a [`PinProvider`]: `env:NAME` reads an environment
+//! variable, and `file:` URIs and plain paths read a file. Applications can add their
+//! own schemes, or replace these, in [`PinProviders::global`].
+//!
+//! Running programs is opt-in, since URIs may come from untrusted sources: register
+//! [`CommandPinProvider`] as `|` to read the PIN from the output of `|command`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use log::debug;
+
+use zeroize::Zeroizing;
+
+use crate::{split_once, SecretPin};
+
+/// Reads PINs for one `pin-source` scheme
+pub trait PinProvider: Send + Sync {
+    /// Returns the PIN that `source`, the part of `pin-source` after the scheme, refers to
+    fn pin(&self, source: &str) -> anyhow::Result<SecretPin>;
+}
+
+/// `env:NAME`, the value of environment variable `NAME`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EnvPinProvider;
+
+impl PinProvider for EnvPinProvider {
+    fn pin(&self, source: &str) -> anyhow::Result<SecretPin> {
+        std::env::var(source)
+            .map(SecretPin::from)
+            .map_err(|err| anyhow!("Failed to read PIN from `{}`: {}", source, err))
+    }
+}
+
+/// `file:` URIs, local only, and paths: the first line of the file, without surrounding
+/// whitespace
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FilePinProvider;
+
+impl PinProvider for FilePinProvider {
+    fn pin(&self, source: &str) -> anyhow::Result<SecretPin> {
+        let path = match source.strip_prefix("//") {
+            Some(rest) => {
+                let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+                if !(authority.is_empty() || authority.eq_ignore_ascii_case("localhost")) {
+                    return Err(anyhow!("Not a local file: `file://{}`", rest));
+                }
+                path
+            }
+            None => source,
+        };
+        let path = percent_encoding::percent_decode_str(path)
+            .decode_utf8()
+            .map_err(|err| anyhow!("Invalid file name `{}`: {}", path, err))?;
+        let content = std::fs::read_to_string(path.as_ref())
+            .map(Zeroizing::new)
+            .map_err(|err| anyhow!("Failed to read PIN from `{}`: {}", path, err))?;
+        Ok(first_line(&content).trim().into())
+    }
+}
+
+/// `|command`: the first line `command` writes to standard output
+///
+/// The command is split at whitespace and run without a shell. It fails if it exits
+/// unsuccessfully or runs longer than the timeout.
+///
+/// Not built in, only applications that trust their URIs should register it:
+///
+/// ```
+/// use pkcs11_uri::{CommandPinProvider, PinProviders};
+///
+/// PinProviders::global().register("|", CommandPinProvider::default());
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct CommandPinProvider {
+    timeout: Duration,
+}
+
+impl CommandPinProvider {
+    pub fn new(timeout: Duration) -> Self {
+        CommandPinProvider { timeout }
+    }
+}
+
+impl Default for CommandPinProvider {
+    /// Ten seconds, enough for a program that asks the user
+    fn default() -> Self {
+        CommandPinProvider::new(Duration::from_secs(10))
+    }
+}
+
+impl PinProvider for CommandPinProvider {
+    fn pin(&self, source: &str) -> anyhow::Result<SecretPin> {
+        let mut words = source.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| anyhow!("`pin-source` names no command"))?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| anyhow!("Failed to run `{}`: {}", program, err))?;
+
+        // read concurrently, so the command does not block on a full pipe
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let reader = std::thread::spawn(move || {
+            let mut output = Zeroizing::new(String::new());
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                if let Err(err) = child.kill() {
+                    debug!("Failed to kill `{}`: {}", program, err);
+                }
+                child.wait()?;
+                return Err(anyhow!(
+                    "`{}` did not exit within {:?}",
+                    program,
+                    self.timeout
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        if !status.success() {
+            return Err(anyhow!("`{}` failed: {}", program, status));
+        }
+        let output = reader
+            .join()
+            .map_err(|_| anyhow!("Failed to read output of `{}`", program))?
+            .map_err(|err| anyhow!("Failed to read output of `{}`: {}", program, err))?;
+        Ok(first_line(&output).into())
+    }
+}
+
+fn first_line(content: &str) -> &str {
+    let line = content.split('\n').next().unwrap_or("");
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+/// `pin-source` providers by scheme, see [`PinProviders::global`]
+///
+/// Besides the process-wide registry, applications can keep their own, and pass them in
+/// [`IdentifyOptions::pin_providers`](crate::IdentifyOptions::pin_providers).
+pub struct PinProviders {
+    providers: RwLock<BTreeMap<String, Arc<dyn PinProvider>>>,
+}
+
+static PIN_PROVIDERS: PinProviders = PinProviders::new();
+
+impl PinProviders {
+    /// A registry with only the built-in providers
+    pub const fn new() -> Self {
+        PinProviders {
+            providers: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// The providers of this process
+    ///
+    /// Besides those registered, `env` and `file` are built in.
+    ///
+    /// ```
+    /// use pkcs11_uri::{PinProvider, PinProviders, SecretPin};
+    ///
+    /// struct Prompt;
+    ///
+    /// impl PinProvider for Prompt {
+    ///     fn pin(&self, prompt: &str) -> anyhow::Result<SecretPin> {
+    ///         // ask the user
+    ///         # Ok(prompt.into())
+    ///     }
+    /// }
+    ///
+    /// PinProviders::global().register("prompt", Prompt);
+    /// // pin-source=prompt:Token%20PIN
+    /// ```
+    pub fn global() -> &'static PinProviders {
+        &PIN_PROVIDERS
+    }
+
+    /// Handles `scheme`, which is case-insensitive, with `provider`, replacing any
+    /// previous provider; `"|"` is the scheme of commands
+    pub fn register(&self, scheme: &str, provider: impl PinProvider + 'static) {
+        self.providers
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(scheme.to_ascii_lowercase(), Arc::new(provider));
+    }
+
+    /// The provider for `scheme`
+    pub fn get(&self, scheme: &str) -> Option<Arc<dyn PinProvider>> {
+        let scheme = scheme.to_ascii_lowercase();
+        let registered = self
+            .providers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&scheme)
+            .cloned();
+        registered.or_else(|| -> Option<Arc<dyn PinProvider>> {
+            match scheme.as_str() {
+                "env" => Some(Arc::new(EnvPinProvider)),
+                "file" => Some(Arc::new(FilePinProvider)),
+                _ => None,
+            }
+        })
+    }
+
+    /// Reads the PIN that a `pin-source` value refers to
+    ///
+    /// Values without a scheme are paths.
+    pub fn pin(&self, pin_source: &str) -> anyhow::Result<SecretPin> {
+        let (scheme, source) = split_scheme(pin_source);
+        let provider = self
+            .get(scheme)
+            .ok_or_else(|| anyhow!("Unknown `pin-source` scheme `{}`", scheme))?;
+        provider.pin(source)
+    }
+}
+
+impl Default for PinProviders {
+    fn default() -> Self {
+        PinProviders::new()
+    }
+}
+
+impl fmt::Debug for PinProviders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let providers = self
+            .providers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        f.debug_struct("PinProviders")
+            .field("schemes", &providers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+fn split_scheme(pin_source: &str) -> (&str, &str) {
+    if let Some(command) = pin_source.strip_prefix('|') {
+        return ("|", command);
+    }
+    // scheme = ALPHA *( ALPHA / DIGIT / "+" / "-" / "." ), as in RFC 3986
+    let is_scheme = |scheme: &str| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    };
+    match split_once(pin_source, ':') {
+        Some((scheme, source)) if is_scheme(scheme) => (scheme, source),
+        _ => ("file", pin_source),
+    }
+}
@@ -0,0 +1,142 @@
+//! Process-wide registry of loaded PKCS #11 modules
+//!
+//! `C_Initialize` may only be called once per library and process, so every module
+//! is loaded and initialized once, and shared by all users until the last one drops
+//! its handle, which calls `C_Finalize`.
+
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use anyhow::anyhow;
+use log::debug;
+
+use crate::SlotId;
+
+/// A loaded and initialized module
+///
+/// Dereferences to the `pkcs11::Ctx` it wraps.
+pub struct Module {
+    path: PathBuf,
+    // registry key
+    key: PathBuf,
+    // only `None` while dropping
+    ctx: Option<pkcs11::Ctx>,
+    logins: Mutex<BTreeMap<SlotId, SlotLogin>>,
+}
+
+/// Login state of a token, which all sessions with it share
+#[derive(Debug)]
+pub(crate) struct SlotLogin {
+    /// Logged-in [`Session`](crate::Session)s relying on the login
+    pub sessions: usize,
+    /// Whether one of them logged in, rather than finding the token logged in
+    pub own: bool,
+}
+
+impl Module {
+    /// The library path the module was loaded from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Logins by slot, locked while logging in or out
+    pub(crate) fn logins(&self) -> MutexGuard<'_, BTreeMap<SlotId, SlotLogin>> {
+        self.logins
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Deref for Module {
+    type Target = pkcs11::Ctx;
+
+    fn deref(&self) -> &pkcs11::Ctx {
+        self.ctx.as_ref().expect("module is finalized")
+    }
+}
+
+impl core::fmt::Debug for Module {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Module").field("path", &self.path).finish()
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        // finalize under the lock, so the module is not initialized again meanwhile
+        let mut modules = REGISTRY.lock();
+        debug!("finalizing module `{}`", self.path.display());
+        drop(self.ctx.take());
+        modules.remove(&self.key);
+    }
+}
+
+/// Loaded modules by path, see [`ModuleRegistry::global`]
+pub struct ModuleRegistry {
+    modules: Mutex<BTreeMap<PathBuf, Weak<Module>>>,
+}
+
+static REGISTRY: ModuleRegistry = ModuleRegistry {
+    modules: Mutex::new(BTreeMap::new()),
+};
+
+impl ModuleRegistry {
+    /// The registry of this process
+    pub fn global() -> &'static ModuleRegistry {
+        &REGISTRY
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<PathBuf, Weak<Module>>> {
+        // the map stays consistent even if a holder panicked
+        self.modules
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Returns the module loaded from `path`, loading and initializing it if necessary
+    ///
+    /// Paths are compared after resolving symbolic links.
+    pub fn load(&self, path: impl AsRef<Path>) -> anyhow::Result<Arc<Module>> {
+        let path = path.as_ref();
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+        let mut modules = loop {
+            let modules = self.lock();
+            match modules.get(&key).map(Weak::upgrade) {
+                Some(Some(module)) => return Ok(module),
+                // the last handle was dropped, wait until the module is finalized
+                Some(None) => {
+                    drop(modules);
+                    std::thread::yield_now();
+                }
+                None => break modules,
+            }
+        };
+
+        debug!("loading module `{}`", path.display());
+        let mut ctx = pkcs11::Ctx::new(path)
+            .map_err(|err| anyhow!("Failed to load module `{}`: {}", path.display(), err))?;
+        // the module is shared between threads
+        let args = pkcs11::types::CK_C_INITIALIZE_ARGS::new();
+        ctx.initialize(Some(args))
+            .map_err(|err| anyhow!("Failed to initialize module `{}`: {}", path.display(), err))?;
+
+        let module = Arc::new(Module {
+            path: path.to_path_buf(),
+            key: key.clone(),
+            ctx: Some(ctx),
+            logins: Mutex::new(BTreeMap::new()),
+        });
+        modules.insert(key, Arc::downgrade(&module));
+        Ok(module)
+    }
+
+    /// Whether the module loaded from `path` is currently initialized
+    pub fn is_loaded(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.lock().contains_key(&key)
+    }
+}
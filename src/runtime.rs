@@ -0,0 +1,589 @@
+//! Loading PKCS #11 modules and looking up the slots, tokens and objects a URI identifies
+
+use core::convert::TryFrom;
+
+use anyhow::anyhow;
+use log::{debug, trace};
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{
+    Module, ModuleRegistry, ModuleResolver, Objects, PinProviders, Pkcs11Uri, SecretPin, Session,
+    SlotId,
+};
+
+/// Shared handle to a loaded module, see [`ModuleRegistry`]
+pub type Context = Arc<Module>;
+pub type SessionHandle = pkcs11::types::CK_SESSION_HANDLE;
+pub type ObjectHandle = pkcs11::types::CK_OBJECT_HANDLE;
+
+/// Which tokens to search when several match a URI
+///
+/// Tokens are ordered by module, in the order they are searched, and then by slot ID.
+/// Tokens that are not initialized yet cannot be searched, so they never count.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SlotPolicy {
+    /// Fail unless exactly one token matches
+    #[default]
+    Unique,
+    /// Use the first matching token
+    First,
+    /// Search every matching token
+    All,
+}
+
+/// How [`Pkcs11Uri::identify_objects_by`] looks up objects
+///
+/// ```no_run
+/// use pkcs11_uri::{IdentifyOptions, ModuleResolver, Pkcs11Uri};
+///
+/// let uri = Pkcs11Uri::try_from("pkcs11:token=my-ca?pin-source=env:SO_PIN").unwrap();
+/// let mut options = IdentifyOptions::default();
+/// options.user_type = pkcs11::types::CKU_SO;
+/// let searches = uri.identify_objects_by(&ModuleResolver::default(), options).unwrap();
+/// ```
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct IdentifyOptions<'a> {
+    pub slot_policy: SlotPolicy,
+    /// `CKU_USER`, or `CKU_SO` for administrative tasks, which needs a read-write session
+    pub user_type: pkcs11::types::CK_USER_TYPE,
+    /// Open read-write sessions, to create, modify or delete objects
+    ///
+    /// Read-only sessions also work with write-protected tokens, and some tokens only
+    /// support few read-write sessions.
+    pub read_write: bool,
+    /// Without a PIN in the URI, log in on the token's PIN pad if it has one
+    ///
+    /// Off by default, since the user would have to enter the PIN for every lookup,
+    /// even of public objects.
+    pub pin_pad: bool,
+    /// Where to read the PIN of `pin-source` from
+    pub pin_providers: &'a PinProviders,
+}
+
+impl Default for IdentifyOptions<'static> {
+    fn default() -> Self {
+        IdentifyOptions {
+            slot_policy: SlotPolicy::Unique,
+            user_type: pkcs11::types::CKU_USER,
+            read_write: false,
+            pin_pad: false,
+            pin_providers: PinProviders::global(),
+        }
+    }
+}
+
+/// Registries compare by identity
+impl PartialEq for IdentifyOptions<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.slot_policy == other.slot_policy
+            && self.user_type == other.user_type
+            && self.read_write == other.read_write
+            && self.pin_pad == other.pin_pad
+            && core::ptr::eq(self.pin_providers, other.pin_providers)
+    }
+}
+
+impl Eq for IdentifyOptions<'_> {}
+
+/// A slot, with the module it belongs to
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ModuleSlot {
+    pub module_path: PathBuf,
+    pub slot_id: SlotId,
+}
+
+impl Pkcs11Uri {
+    fn matches_library(&self, ctx: &pkcs11::Ctx) -> anyhow::Result<bool> {
+        // library_description, library_manufacturer, library_version
+
+        let info = ctx.get_info()?;
+        trace!("{:?}", info);
+
+        if let Some(library_description) = &self.path_attributes.library_description {
+            if library_description != String::from(info.libraryDescription).as_str() {
+                trace!("failed library_description check");
+                return Ok(false);
+            }
+        }
+        if let Some(library_manufacturer) = &self.path_attributes.library_manufacturer {
+            if library_manufacturer != String::from(info.manufacturerID).as_str() {
+                trace!("failed library_manufacturer check");
+                return Ok(false);
+            }
+        }
+        if let Some(library_version) = self.path_attributes.library_version {
+            let version = info.libraryVersion;
+            if (library_version.major, library_version.minor) != (version.major, version.minor) {
+                trace!("failed library_version check");
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn matches_slot(
+        &self,
+        ctx: &pkcs11::Ctx,
+        slot_id: pkcs11::types::CK_SLOT_ID,
+    ) -> anyhow::Result<bool> {
+        // slot_id, slot_description, slot_manufacturer
+
+        if let Some(uri_slot_id) = self.path_attributes.slot_id {
+            if uri_slot_id != slot_id {
+                return Ok(false);
+            }
+        }
+        let info = ctx.get_slot_info(slot_id)?;
+        trace!("{:?}", info);
+
+        if let Some(slot_description) = &self.path_attributes.slot_description {
+            if slot_description != String::from(info.slotDescription).as_str() {
+                return Ok(false);
+            }
+        }
+        if let Some(slot_manufacturer) = &self.path_attributes.slot_manufacturer {
+            if slot_manufacturer != String::from(info.manufacturerID).as_str() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn matches_token(
+        &self,
+        ctx: &pkcs11::Ctx,
+        slot_id: pkcs11::types::CK_SLOT_ID,
+    ) -> anyhow::Result<bool> {
+        // token_manufacturer, token_model, token_label, token_serial
+
+        let info = ctx.get_token_info(slot_id)?;
+        trace!("{:?}", info);
+
+        if let Some(token_manufacturer) = &self.path_attributes.token_manufacturer {
+            if token_manufacturer != String::from(info.manufacturerID).as_str() {
+                trace!("failed token_manufacturer check");
+                return Ok(false);
+            }
+        }
+        if let Some(token_model) = &self.path_attributes.token_model {
+            if token_model != String::from(info.model).as_str() {
+                trace!("failed token_model check");
+                return Ok(false);
+            }
+        }
+        if let Some(token_label) = &self.path_attributes.token_label {
+            if token_label != String::from(info.label).as_str() {
+                trace!("failed token_label check");
+                return Ok(false);
+            }
+        }
+        if let Some(token_serial) = &self.path_attributes.token_serial {
+            if token_serial != &info.serialNumber.0 {
+                trace!("failed token_serial check");
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn matching_slots(&self, ctx: &Context) -> anyhow::Result<Vec<SlotId>> {
+        let mut slots = Vec::new();
+        if !self.matches_library(ctx)? {
+            return Ok(slots);
+        }
+        for slot in ctx.get_slot_list(true)? {
+            if self.matches_slot(ctx, slot)? {
+                slots.push(slot);
+            }
+        }
+        Ok(slots)
+    }
+
+    fn matching_tokens(&self, ctx: &Context) -> anyhow::Result<Vec<SlotId>> {
+        let mut slots = Vec::new();
+        for slot in self.matching_slots(ctx)? {
+            if self.matches_token(ctx, slot)? {
+                slots.push(slot);
+            }
+        }
+        Ok(slots)
+    }
+
+    /// Matching tokens that are initialized, so that sessions can be opened with them
+    fn initialized_tokens(&self, ctx: &Context) -> anyhow::Result<Vec<SlotId>> {
+        let mut slots = Vec::new();
+        for slot in self.matching_tokens(ctx)? {
+            let info = ctx.get_token_info(slot)?;
+            if info.flags & pkcs11::types::CKF_TOKEN_INITIALIZED == 0 {
+                debug!("skipping uninitialized token in slot {}", slot);
+                continue;
+            }
+            slots.push(slot);
+        }
+        Ok(slots)
+    }
+
+    /// The module of `module-path`, or else of `module-name` as resolved by
+    /// [`ModuleResolver::default`], loaded once per process
+    pub fn context(&self) -> anyhow::Result<Context> {
+        self.context_with(&ModuleResolver::default())
+    }
+
+    /// [`context`](Self::context), resolving `module-name` with `resolver`
+    pub fn context_with(&self, resolver: &ModuleResolver) -> anyhow::Result<Context> {
+        load(&self.module_path_with(resolver)?)
+    }
+
+    /// The library to load: `module-path` if given, else `module-name` resolved by `resolver`
+    pub fn module_path_with(&self, resolver: &ModuleResolver) -> anyhow::Result<PathBuf> {
+        if let Some(module_path) = &self.query_attributes.module_path {
+            return Ok(module_path.into());
+        }
+        let module_name = self
+            .query_attributes
+            .module_name
+            .as_ref()
+            .ok_or_else(|| anyhow!("URI has neither `module-path` nor `module-name`"))?;
+        let module = resolver.resolve(module_name)?;
+        debug!("module `{}`: {:?}", module_name, module);
+        Ok(module.path)
+    }
+
+    fn names_module(&self) -> bool {
+        let query = &self.query_attributes;
+        query.module_path.is_some() || query.module_name.is_some()
+    }
+
+    /// Loads the module the URI names, or else every module `resolver` searches
+    ///
+    /// When searching, modules that fail to load are skipped.
+    fn contexts_with(&self, resolver: &ModuleResolver) -> anyhow::Result<Vec<Context>> {
+        if self.names_module() {
+            return Ok(vec![self.context_with(resolver)?]);
+        }
+
+        let module_paths = resolver.search_modules();
+        if module_paths.is_empty() {
+            return Err(anyhow!(
+                "URI names no module, and no modules are registered"
+            ));
+        }
+        let mut contexts = Vec::new();
+        for module_path in module_paths {
+            match load(&module_path) {
+                Ok(ctx) => contexts.push(ctx),
+                Err(err) => debug!("skipping: {}", err),
+            }
+        }
+        if contexts.is_empty() {
+            return Err(anyhow!("None of the modules to search could be loaded"));
+        }
+        Ok(contexts)
+    }
+
+    /// The slots `matching` finds in each module of [`contexts_with`](Self::contexts_with)
+    ///
+    /// When searching, modules that fail to list their slots or tokens are skipped,
+    /// like those that fail to load.
+    fn module_slots_with(
+        &self,
+        resolver: &ModuleResolver,
+        matching: impl Fn(&Self, &Context) -> anyhow::Result<Vec<SlotId>>,
+    ) -> anyhow::Result<Vec<(Context, Vec<SlotId>)>> {
+        let mut module_slots = Vec::new();
+        let mut error = None;
+        for ctx in self.contexts_with(resolver)? {
+            match matching(self, &ctx) {
+                Ok(slots) => module_slots.push((ctx, slots)),
+                Err(err) if !self.names_module() => {
+                    debug!("skipping `{}`: {}", ctx.path().display(), err);
+                    error = Some(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        match error {
+            Some(err) if module_slots.is_empty() => Err(err),
+            _ => Ok(module_slots),
+        }
+    }
+
+    /// Slots matching the URI, in the module it names or else in all registered modules
+    pub fn identify_slots(&self) -> anyhow::Result<Vec<ModuleSlot>> {
+        self.identify_slots_with(&ModuleResolver::default())
+    }
+
+    /// [`identify_slots`](Self::identify_slots), finding modules with `resolver`
+    pub fn identify_slots_with(
+        &self,
+        resolver: &ModuleResolver,
+    ) -> anyhow::Result<Vec<ModuleSlot>> {
+        let mut slots = Vec::new();
+        for (ctx, slot_ids) in self.module_slots_with(resolver, Self::matching_slots)? {
+            for slot_id in slot_ids {
+                slots.push(ModuleSlot {
+                    module_path: ctx.path().to_path_buf(),
+                    slot_id,
+                });
+            }
+        }
+        Ok(slots)
+    }
+
+    /// Slots with tokens matching the URI, in the module it names or else in all
+    /// registered modules
+    pub fn identify_tokens(&self) -> anyhow::Result<Vec<ModuleSlot>> {
+        self.identify_tokens_with(&ModuleResolver::default())
+    }
+
+    /// [`identify_tokens`](Self::identify_tokens), finding modules with `resolver`
+    pub fn identify_tokens_with(
+        &self,
+        resolver: &ModuleResolver,
+    ) -> anyhow::Result<Vec<ModuleSlot>> {
+        let mut slots = Vec::new();
+        for (ctx, slot_ids) in self.module_slots_with(resolver, Self::matching_tokens)? {
+            for slot_id in slot_ids {
+                slots.push(ModuleSlot {
+                    module_path: ctx.path().to_path_buf(),
+                    slot_id,
+                });
+            }
+        }
+        Ok(slots)
+    }
+
+    /// The single object matching the URI, with a session logged in as the URI says
+    ///
+    /// The session is read-only, see [`IdentifyOptions::read_write`], and logs out and
+    /// closes when dropped.
+    pub fn identify_object(&self) -> anyhow::Result<(Session, ObjectHandle)> {
+        self.identify_object_with(&ModuleResolver::default())
+    }
+
+    /// [`identify_object`](Self::identify_object), finding modules with `resolver`
+    pub fn identify_object_with(
+        &self,
+        resolver: &ModuleResolver,
+    ) -> anyhow::Result<(Session, ObjectHandle)> {
+        self.identify_object_by(resolver, IdentifyOptions::default())
+    }
+
+    /// [`identify_object_with`](Self::identify_object_with), following `options`
+    ///
+    /// With [`SlotPolicy::All`], the object must be unique across all matching tokens.
+    pub fn identify_object_by(
+        &self,
+        resolver: &ModuleResolver,
+        options: IdentifyOptions<'_>,
+    ) -> anyhow::Result<(Session, ObjectHandle)> {
+        let mut found = None;
+        let mut count = 0;
+        for mut objects in self.identify_objects_by(resolver, options)? {
+            let object = match objects.next() {
+                Some(object) => object?,
+                None => continue,
+            };
+            count += 1;
+            for other in objects.by_ref() {
+                other?;
+                count += 1;
+            }
+            if found.is_none() {
+                found = Some((objects.into_session(), object));
+            }
+        }
+        match found {
+            Some(_) if count > 1 => Err(anyhow!(
+                "URI `{}` matches {} objects instead of one",
+                self.redacted(),
+                count
+            )),
+            Some(found) => Ok(found),
+            None => Err(anyhow!("No objects found for URI `{}`", self.redacted())),
+        }
+    }
+
+    /// All objects matching the URI, with a session logged in as the URI says
+    ///
+    /// ```no_run
+    /// use pkcs11_uri::Pkcs11Uri;
+    ///
+    /// let uri = Pkcs11Uri::try_from("pkcs11:token=my-ca;type=cert").unwrap();
+    /// let mut objects = uri.identify_objects().unwrap();
+    /// let certificates: Vec<_> = objects.by_ref().collect::<Result<_, _>>().unwrap();
+    /// let session = objects.into_session();
+    /// ```
+    pub fn identify_objects(&self) -> anyhow::Result<Objects> {
+        self.identify_objects_with(&ModuleResolver::default())
+    }
+
+    /// [`identify_objects`](Self::identify_objects), finding modules with `resolver`
+    pub fn identify_objects_with(&self, resolver: &ModuleResolver) -> anyhow::Result<Objects> {
+        let mut searches = self.identify_objects_by(resolver, IdentifyOptions::default())?;
+        Ok(searches.remove(0))
+    }
+
+    /// One search per token matching the URI, chosen by the slot policy of `options`,
+    /// in slot order
+    ///
+    /// Each search has its own session; [`Objects::slot`] tells which token it is on.
+    ///
+    /// ```no_run
+    /// use pkcs11_uri::{IdentifyOptions, ModuleResolver, Pkcs11Uri, SlotPolicy};
+    ///
+    /// let uri = Pkcs11Uri::try_from("pkcs11:token=partition;type=cert").unwrap();
+    /// let mut options = IdentifyOptions::default();
+    /// options.slot_policy = SlotPolicy::All;
+    /// for objects in uri.identify_objects_by(&ModuleResolver::default(), options).unwrap() {
+    ///     let slot = objects.slot();
+    ///     for object in objects {
+    ///         println!("{:?}: {}", slot, object.unwrap());
+    ///     }
+    /// }
+    /// ```
+    pub fn identify_objects_by(
+        &self,
+        resolver: &ModuleResolver,
+        options: IdentifyOptions<'_>,
+    ) -> anyhow::Result<Vec<Objects>> {
+        // object_class: Option<ObjectClass>
+        // object_id: Option<Vec<u8>>
+        // object_label: Option<String>
+
+        type Attribute = pkcs11::types::CK_ATTRIBUTE;
+        let mut template = Vec::<Attribute>::new();
+        if let Some(object_label) = &self.path_attributes.object_label {
+            template.push(Attribute::new(pkcs11::types::CKA_LABEL).with_string(object_label));
+        }
+        if let Some(object_id) = &self.path_attributes.object_id {
+            template.push(Attribute::new(pkcs11::types::CKA_ID).with_bytes(object_id.as_ref()));
+        }
+        let raw_object_class = self
+            .path_attributes
+            .object_class
+            .map(pkcs11::types::CK_OBJECT_CLASS::try_from)
+            .transpose()
+            .map_err(|class| anyhow!("Object class {:#x} does not fit `CK_ULONG`", class))?;
+        if let Some(raw_object_class) = &raw_object_class {
+            template.push(Attribute::new(pkcs11::types::CKA_CLASS).with_ck_ulong(raw_object_class));
+        }
+
+        // the security officer only has a read-write state
+        let flags = if options.read_write || options.user_type == pkcs11::types::CKU_SO {
+            pkcs11::types::CKF_RW_SESSION
+        } else {
+            0
+        };
+        let mut searches = Vec::new();
+        for (ctx, slot) in self.token_slots_with(resolver, options.slot_policy)? {
+            let mut session = Session::open(ctx, slot, flags)?;
+            self.login(&mut session, options)?;
+            searches.push(Objects::find(session, &template)?);
+        }
+        Ok(searches)
+    }
+
+    /// Slots with initialized tokens matching the URI, chosen by `policy`
+    fn token_slots_with(
+        &self,
+        resolver: &ModuleResolver,
+        policy: SlotPolicy,
+    ) -> anyhow::Result<Vec<(Context, SlotId)>> {
+        let mut candidates = Vec::new();
+        for (ctx, mut slots) in self.module_slots_with(resolver, Self::initialized_tokens)? {
+            debug!("slots of `{}`: {:?}", ctx.path().display(), slots);
+            slots.sort_unstable();
+            candidates.extend(slots.into_iter().map(|slot| (ctx.clone(), slot)));
+        }
+
+        if candidates.is_empty() {
+            return Err(anyhow!("No slots found for URI `{}`", self.redacted()));
+        }
+        match policy {
+            SlotPolicy::Unique if candidates.len() > 1 => {
+                return Err(anyhow!(
+                    "{} tokens match the URI, but the slot policy requires a unique one",
+                    candidates.len()
+                ));
+            }
+            SlotPolicy::First => candidates.truncate(1),
+            _ => {}
+        }
+        Ok(candidates)
+    }
+
+    /// Logs in with the PIN of the URI, or on the token's own PIN pad if it has one and
+    /// `options` allow it
+    ///
+    /// Without either, the session is left as it is.
+    fn login(&self, session: &mut Session, options: IdentifyOptions<'_>) -> anyhow::Result<()> {
+        match self.pin_with(options.pin_providers)? {
+            Some(pin) => session.login(options.user_type, Some(pin.expose_secret())),
+            None if options.pin_pad && session.has_protected_authentication_path()? => {
+                session.login(options.user_type, None)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// `pin-value`, or else the PIN `pin-source` refers to, read with
+    /// [`PinProviders::global`]
+    pub fn pin(&self) -> anyhow::Result<Option<SecretPin>> {
+        self.pin_with(PinProviders::global())
+    }
+
+    /// [`pin`](Self::pin), reading `pin-source` with `providers`
+    pub fn pin_with(&self, providers: &PinProviders) -> anyhow::Result<Option<SecretPin>> {
+        if let Some(pin) = &self.query_attributes.pin_value {
+            return Ok(Some(pin.clone()));
+        }
+        match &self.query_attributes.pin_source {
+            Some(source) => providers.pin(source).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Logs in for a single operation on `object`, if it has `CKA_ALWAYS_AUTHENTICATE`
+    ///
+    /// Such keys need a `CKU_CONTEXT_SPECIFIC` login, with the PIN of the URI or on the
+    /// token's PIN pad, after each call like `C_SignInit` and before the operation.
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use pkcs11_uri::Pkcs11Uri;
+    ///
+    /// let uri = Pkcs11Uri::try_from("pkcs11:object=signing-key;type=private?pin-value=1234")?;
+    /// let (session, key) = uri.identify_object()?;
+    /// let mechanism = pkcs11::types::CK_MECHANISM {
+    ///     mechanism: pkcs11::types::CKM_SHA256_RSA_PKCS,
+    ///     pParameter: std::ptr::null_mut(),
+    ///     ulParameterLen: 0,
+    /// };
+    /// session.module().sign_init(session.handle(), &mechanism, key)?;
+    /// uri.authenticate(&session, key)?;
+    /// let signature = session.module().sign(session.handle(), b"data")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn authenticate(&self, session: &Session, object: ObjectHandle) -> anyhow::Result<()> {
+        if !session.always_authenticate(object)? {
+            return Ok(());
+        }
+        match self.pin()? {
+            Some(pin) => session.authenticate(Some(pin.expose_secret())),
+            None if session.has_protected_authentication_path()? => session.authenticate(None),
+            None => Err(anyhow!(
+                "The object needs a PIN for every operation, but the URI has none"
+            )),
+        }
+    }
+}
+
+fn load(module_path: &Path) -> anyhow::Result<Context> {
+    ModuleRegistry::global().load(module_path)
+}
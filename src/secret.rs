@@ -0,0 +1,150 @@
+//! PINs that are wiped from memory and kept out of logs, and URIs without them
+
+use alloc::borrow::Cow;
+use alloc::string::{String, ToString};
+use core::fmt;
+
+use zeroize::Zeroize;
+
+use crate::{Pkcs11Uri, QueryAttributes};
+
+/// A PIN, zeroized when dropped
+///
+/// `Debug` and the structured serde form show [`SecretPin::PLACEHOLDER`] instead of
+/// the PIN; only [`expose_secret`](SecretPin::expose_secret) reveals it.
+///
+/// ```
+/// use pkcs11_uri::Pkcs11Uri;
+///
+/// let uri = Pkcs11Uri::try_from("pkcs11:token=my-ca?pin-value=1234").unwrap();
+/// let pin = uri.query_attributes.pin_value.as_ref().unwrap();
+/// assert_eq!(pin.expose_secret(), "1234");
+/// assert_eq!(format!("{:?}", pin), "SecretPin(***)");
+/// ```
+#[derive(Clone, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SecretPin(String);
+
+impl SecretPin {
+    /// Shown instead of PINs
+    pub const PLACEHOLDER: &'static str = "***";
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretPin {
+    fn from(pin: String) -> Self {
+        SecretPin(pin)
+    }
+}
+
+impl From<&str> for SecretPin {
+    fn from(pin: &str) -> Self {
+        SecretPin(pin.into())
+    }
+}
+
+impl From<Cow<'_, str>> for SecretPin {
+    fn from(pin: Cow<'_, str>) -> Self {
+        SecretPin(pin.into_owned())
+    }
+}
+
+impl fmt::Debug for SecretPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretPin({})", SecretPin::PLACEHOLDER)
+    }
+}
+
+impl Drop for SecretPin {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// What [`Pkcs11Uri::redacted_with`] leaves out
+///
+/// By default, only `pin-value` is replaced.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct RedactOptions {
+    /// Remove `pin-source`, which may name a file or command
+    pub strip_pin_source: bool,
+    /// Remove `module-path`, which reveals the local installation
+    pub strip_module_path: bool,
+    /// Remove the whole query component, leaving only the path that identifies objects
+    pub strip_query: bool,
+}
+
+impl RedactOptions {
+    /// Removes `pin-source` and `module-path` as well
+    pub const fn shareable() -> Self {
+        RedactOptions {
+            strip_pin_source: true,
+            strip_module_path: true,
+            strip_query: false,
+        }
+    }
+
+    /// Removes the query component
+    pub const fn path_only() -> Self {
+        RedactOptions {
+            strip_pin_source: true,
+            strip_module_path: true,
+            strip_query: true,
+        }
+    }
+}
+
+impl Pkcs11Uri {
+    /// The URI with `pin-value` replaced by [`SecretPin::PLACEHOLDER`], for logs and
+    /// error messages
+    ///
+    /// ```
+    /// use pkcs11_uri::{Pkcs11Uri, RedactOptions};
+    ///
+    /// let uri = Pkcs11Uri::try_from(
+    ///     "pkcs11:token=my-ca;object=my-key?pin-value=1234&module-path=/usr/lib/libsofthsm2.so",
+    /// )
+    /// .unwrap();
+    /// assert_eq!(
+    ///     uri.redacted(),
+    ///     "pkcs11:token=my-ca;object=my-key?pin-value=***&module-path=/usr/lib/libsofthsm2.so"
+    /// );
+    /// assert_eq!(
+    ///     uri.redacted_with(RedactOptions::shareable()),
+    ///     "pkcs11:token=my-ca;object=my-key?pin-value=***"
+    /// );
+    /// assert_eq!(
+    ///     uri.redacted_with(RedactOptions::path_only()),
+    ///     "pkcs11:token=my-ca;object=my-key"
+    /// );
+    /// ```
+    pub fn redacted(&self) -> String {
+        self.redacted_with(RedactOptions::default())
+    }
+
+    /// [`redacted`](Self::redacted), leaving out more as `options` say
+    pub fn redacted_with(&self, options: RedactOptions) -> String {
+        let mut query_attributes = if options.strip_query {
+            QueryAttributes::default()
+        } else {
+            self.query_attributes.clone()
+        };
+        if query_attributes.pin_value.is_some() {
+            query_attributes.pin_value = Some(SecretPin::PLACEHOLDER.into());
+        }
+        if options.strip_pin_source {
+            query_attributes.pin_source = None;
+        }
+        if options.strip_module_path {
+            query_attributes.module_path = None;
+        }
+        Pkcs11Uri {
+            path_attributes: self.path_attributes.clone(),
+            query_attributes,
+        }
+        .to_string()
+    }
+}
@@ -0,0 +1,85 @@
+//! `Pkcs11Uri` and `ObjectClass` (de)serialize as their string form; the attribute
+//! structs and `Version` use a structured form, which is checked like
+//! `Pkcs11UriBuilder::build` when deserialized. PINs serialize as
+//! [`SecretPin::PLACEHOLDER`], so serialized URIs can be stored and logged, but do not
+//! round-trip: deserializing rejects the placeholder rather than taking it for the PIN.
+//! To keep the PIN, serialize `uri.to_string()` instead.
+
+use core::convert::TryFrom;
+use core::fmt;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{ObjectClass, Pkcs11Uri, SecretPin};
+
+struct StrVisitor<T>(&'static str, core::marker::PhantomData<T>);
+
+impl<'de, T> de::Visitor<'de> for StrVisitor<T>
+where
+    T: for<'a> TryFrom<&'a str>,
+    for<'a> <T as TryFrom<&'a str>>::Error: fmt::Display,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+        T::try_from(value).map_err(E::custom)
+    }
+}
+
+/// Serializes [`redacted`](Pkcs11Uri::redacted), so `pin-value` is lost
+impl Serialize for Pkcs11Uri {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.redacted())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pkcs11Uri {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let uri: Pkcs11Uri = deserializer
+            .deserialize_str(StrVisitor("a PKCS #11 URI", core::marker::PhantomData))?;
+        match &uri.query_attributes.pin_value {
+            Some(pin) if pin.expose_secret() == SecretPin::PLACEHOLDER => {
+                Err(de::Error::custom(REDACTED_PIN))
+            }
+            _ => Ok(uri),
+        }
+    }
+}
+
+impl Serialize for ObjectClass {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ObjectClass {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(StrVisitor(
+            "a PKCS #11 object class",
+            core::marker::PhantomData,
+        ))
+    }
+}
+
+/// Serializes [`SecretPin::PLACEHOLDER`], so the PIN is lost
+impl Serialize for SecretPin {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(SecretPin::PLACEHOLDER)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretPin {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pin = alloc::string::String::deserialize(deserializer).map(SecretPin::from)?;
+        if pin.expose_secret() == SecretPin::PLACEHOLDER {
+            return Err(de::Error::custom(REDACTED_PIN));
+        }
+        Ok(pin)
+    }
+}
+
+const REDACTED_PIN: &str = "`pin-value` was redacted when serialized, the PIN is unknown";
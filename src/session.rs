@@ -0,0 +1,154 @@
+//! Sessions that log out and close themselves
+
+use anyhow::anyhow;
+use log::debug;
+use pkcs11::types::{
+    CKA_ALWAYS_AUTHENTICATE, CKF_PROTECTED_AUTHENTICATION_PATH, CKF_SERIAL_SESSION, CKR_OK,
+    CKR_USER_ALREADY_LOGGED_IN, CKU_CONTEXT_SPECIFIC, CK_ATTRIBUTE, CK_BBOOL, CK_FALSE, CK_FLAGS,
+    CK_USER_TYPE,
+};
+
+use crate::registry::SlotLogin;
+use crate::{Context, ObjectHandle, SessionHandle, SlotId};
+
+/// An open session, holding on to its module
+///
+/// Dropping the session closes it. Login state is shared by all sessions of a process
+/// with the same token, so the token is logged out once the last session that logged
+/// in is dropped, and only if one of them logged in rather than finding the token
+/// already logged in.
+#[derive(Debug)]
+pub struct Session {
+    module: Context,
+    slot_id: SlotId,
+    handle: SessionHandle,
+    /// Whether the session counts among those relying on the token's login
+    logged_in: bool,
+}
+
+impl Session {
+    /// Opens a session with the token in `slot_id`; `CKF_SERIAL_SESSION` is always set
+    pub fn open(module: Context, slot_id: SlotId, flags: CK_FLAGS) -> anyhow::Result<Self> {
+        let handle = module
+            .open_session(slot_id, flags | CKF_SERIAL_SESSION, None, None)
+            .map_err(|err| anyhow!("Failed to open session with slot {}: {}", slot_id, err))?;
+        Ok(Session {
+            module,
+            slot_id,
+            handle,
+            logged_in: false,
+        })
+    }
+
+    /// The module the session belongs to
+    pub fn module(&self) -> &Context {
+        &self.module
+    }
+
+    pub fn slot_id(&self) -> SlotId {
+        self.slot_id
+    }
+
+    /// The handle to pass to the functions of [`module`](Self::module)
+    pub fn handle(&self) -> SessionHandle {
+        self.handle
+    }
+
+    pub fn is_logged_in(&self) -> bool {
+        self.logged_in
+    }
+
+    /// Whether the token reads PINs itself, on a PIN pad, so that logins pass no PIN
+    pub fn has_protected_authentication_path(&self) -> anyhow::Result<bool> {
+        let info = self
+            .module
+            .get_token_info(self.slot_id)
+            .map_err(|err| anyhow!("Failed to get token info of slot {}: {}", self.slot_id, err))?;
+        Ok(info.flags & CKF_PROTECTED_AUTHENTICATION_PATH != 0)
+    }
+
+    /// Logs in; a token that is already logged in counts as success
+    ///
+    /// A `None` PIN is for tokens with a [protected authentication
+    /// path](Self::has_protected_authentication_path). Use
+    /// [`authenticate`](Self::authenticate) for `CKU_CONTEXT_SPECIFIC`.
+    pub fn login(&mut self, user_type: CK_USER_TYPE, pin: Option<&str>) -> anyhow::Result<()> {
+        let mut logins = self.module.logins();
+        let own = match self.module.login(self.handle, user_type, pin) {
+            Ok(()) => true,
+            Err(pkcs11::errors::Error::Pkcs11(CKR_USER_ALREADY_LOGGED_IN)) => {
+                debug!("slot {} is already logged in", self.slot_id);
+                false
+            }
+            Err(err) => return Err(anyhow!("Failed to log in: {}", err)),
+        };
+        let login = logins.entry(self.slot_id).or_insert(SlotLogin {
+            sessions: 0,
+            own: false,
+        });
+        login.own |= own;
+        if !self.logged_in {
+            login.sessions += 1;
+            self.logged_in = true;
+        }
+        Ok(())
+    }
+
+    /// Whether every operation with `object` needs a [context-specific
+    /// login](Self::authenticate)
+    pub fn always_authenticate(&self, object: ObjectHandle) -> anyhow::Result<bool> {
+        let value: CK_BBOOL = CK_FALSE;
+        let mut template = vec![CK_ATTRIBUTE::new(CKA_ALWAYS_AUTHENTICATE).with_bool(&value)];
+        let (rv, attributes) = self
+            .module
+            .get_attribute_value(self.handle, object, &mut template)
+            .map_err(|err| anyhow!("Failed to read object attributes: {}", err))?;
+        // objects other than private keys lack the attribute
+        Ok(rv == CKR_OK && attributes[0].get_bool().unwrap_or(false))
+    }
+
+    /// Logs in as `CKU_CONTEXT_SPECIFIC` for the operation just initialized
+    ///
+    /// This does not change the login state of the session.
+    pub fn authenticate(&self, pin: Option<&str>) -> anyhow::Result<()> {
+        self.module
+            .login(self.handle, CKU_CONTEXT_SPECIFIC, pin)
+            .map_err(|err| anyhow!("Failed to authenticate operation: {}", err))
+    }
+
+    /// Stops relying on the token's login, logging out if no other session does
+    pub fn logout(&mut self) -> anyhow::Result<()> {
+        if !self.logged_in {
+            return Ok(());
+        }
+        self.logged_in = false;
+        let mut logins = self.module.logins();
+        let login = match logins.get_mut(&self.slot_id) {
+            Some(login) => login,
+            None => return Ok(()),
+        };
+        login.sessions -= 1;
+        if login.sessions > 0 {
+            return Ok(());
+        }
+        let own = login.own;
+        logins.remove(&self.slot_id);
+        if own {
+            self.module
+                .logout(self.handle)
+                .map_err(|err| anyhow!("Failed to log out: {}", err))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Err(err) = self.logout() {
+            debug!("{}", err);
+        }
+        if let Err(err) = self.module.close_session(self.handle) {
+            debug!("Failed to close session: {}", err);
+        }
+    }
+}
@@ -1,7 +1,13 @@
+use crate::{Pkcs11Uri, QueryAttributes};
+use core::convert::TryFrom;
+#[cfg(feature = "runtime")]
 use pkcs11::Ctx;
+#[cfg(feature = "runtime")]
 use serial_test::serial;
+#[cfg(feature = "runtime")]
 use std::path::PathBuf;
 
+#[cfg(feature = "runtime")]
 fn pkcs11_module_name() -> PathBuf {
     let path =
         std::env::var_os("PKCS11_MODULE").unwrap_or_else(|| "/usr/lib/libsofthsm2.so".into());
@@ -13,6 +19,7 @@ fn pkcs11_module_name() -> PathBuf {
 }
 
 #[test]
+#[cfg(feature = "runtime")]
 #[serial]
 fn new_then_initialize() {
     let mut session = Ctx::new(pkcs11_module_name()).unwrap();
@@ -29,6 +36,7 @@ fn new_then_initialize() {
 }
 
 #[test]
+#[cfg(feature = "runtime")]
 #[serial]
 fn new_and_initialize() {
     let result = Ctx::new_and_initialize(pkcs11_module_name());
@@ -38,3 +46,1162 @@ fn new_and_initialize() {
         result.unwrap_err()
     );
 }
+
+#[test]
+#[cfg(feature = "runtime")]
+#[serial]
+fn softhsm_slot_and_library_matching() {
+    use crate::{ModuleResolver, ModuleSlot, Version};
+
+    let module_path = pkcs11_module_name().to_str().unwrap().to_string();
+    let builder = || Pkcs11Uri::builder().module_path(module_path.as_str());
+
+    let ctx = builder().build().unwrap().context().unwrap();
+    let slots = ctx.get_slot_list(true).unwrap();
+    let info = ctx.get_info().unwrap();
+    drop(ctx);
+
+    // `slot-id` selects exactly that slot
+    let module_slots = |slots: &[crate::SlotId]| {
+        slots
+            .iter()
+            .map(|&slot_id| ModuleSlot {
+                module_path: module_path.clone().into(),
+                slot_id,
+            })
+            .collect::<Vec<_>>()
+    };
+    let uri = builder().slot_id(slots[0]).build().unwrap();
+    assert_eq!(uri.identify_slots().unwrap(), module_slots(&slots[..1]));
+    let unused = slots.iter().max().unwrap() + 1;
+    let uri = builder().slot_id(unused).build().unwrap();
+    assert!(uri.identify_slots().unwrap().is_empty());
+
+    // library attributes are checked against `C_GetInfo`
+    let library_version = Version {
+        major: info.libraryVersion.major,
+        minor: info.libraryVersion.minor,
+    };
+    let uri = builder()
+        .library_description(String::from(info.libraryDescription))
+        .library_manufacturer(String::from(info.manufacturerID))
+        .library_version(library_version)
+        .build()
+        .unwrap();
+    assert_eq!(uri.identify_slots().unwrap(), module_slots(&slots));
+
+    let wrong_version = Version {
+        major: library_version.major.wrapping_add(1),
+        minor: library_version.minor,
+    };
+    let uri = builder().library_version(wrong_version).build().unwrap();
+    assert!(uri.identify_slots().unwrap().is_empty());
+    let uri = builder()
+        .library_manufacturer("Snake Oil, Inc.")
+        .build()
+        .unwrap();
+    assert!(uri.identify_slots().unwrap().is_empty());
+    let uri = builder()
+        .library_description("not the library")
+        .build()
+        .unwrap();
+    assert!(uri.identify_tokens().unwrap().is_empty());
+
+    // without a module, all modules to search are searched, skipping those that fail
+    let resolver = ModuleResolver::new()
+        .search_module("/nonexistent/libpkcs11.so")
+        .search_module(&module_path);
+    let uri = Pkcs11Uri::builder().slot_id(slots[0]).build().unwrap();
+    assert_eq!(
+        uri.identify_slots_with(&resolver).unwrap(),
+        module_slots(&slots[..1])
+    );
+}
+
+#[test]
+#[cfg(feature = "runtime")]
+#[serial]
+fn softhsm_module_registry() {
+    use crate::ModuleRegistry;
+    use std::sync::Arc;
+
+    let module_path = pkcs11_module_name();
+    let registry = ModuleRegistry::global();
+    let uri = Pkcs11Uri::builder()
+        .module_path(module_path.to_str().unwrap())
+        .build()
+        .unwrap();
+
+    let first = registry.load(&module_path).unwrap();
+    let second = uri.context().unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+    // identification reuses the initialized module
+    assert!(!uri.identify_slots().unwrap().is_empty());
+    assert!(!uri.identify_tokens().unwrap().is_empty());
+
+    drop(first);
+    assert!(registry.is_loaded(&module_path));
+    drop(second);
+    assert!(!registry.is_loaded(&module_path));
+
+    // and can be initialized again after the last handle is gone
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let uri = uri.clone();
+            std::thread::spawn(move || uri.identify_slots().unwrap().len())
+        })
+        .collect();
+    for thread in threads {
+        assert!(thread.join().unwrap() > 0);
+    }
+    assert!(!registry.is_loaded(&module_path));
+
+    assert!(registry.load("/nonexistent/libpkcs11.so").is_err());
+    assert!(!registry.is_loaded("/nonexistent/libpkcs11.so"));
+}
+
+#[test]
+#[cfg(feature = "runtime")]
+#[serial]
+fn softhsm_session_logs_out_and_closes() {
+    // the token of the examples, see README.md
+    use crate::Session;
+    use pkcs11::types::{CKS_RO_PUBLIC_SESSION, CKS_RO_USER_FUNCTIONS, CKU_USER};
+
+    let uri = Pkcs11Uri::builder()
+        .token_label("my-ca")
+        .module_path(pkcs11_module_name().to_str().unwrap())
+        .build()
+        .unwrap();
+    let slot_id = uri.identify_tokens().unwrap()[0].slot_id;
+    let ctx = uri.context().unwrap();
+    let state = |session: &Session| ctx.get_session_info(session.handle()).unwrap().state;
+
+    let mut session = Session::open(ctx.clone(), slot_id, 0).unwrap();
+    assert!(!session.is_logged_in());
+    session.login(CKU_USER, Some("1234")).unwrap();
+    assert!(session.is_logged_in());
+    assert_eq!(state(&session), CKS_RO_USER_FUNCTIONS);
+
+    // CKR_USER_ALREADY_LOGGED_IN counts as success, without taking over the login
+    let mut other = Session::open(ctx.clone(), slot_id, 0).unwrap();
+    other.login(CKU_USER, Some("1234")).unwrap();
+    assert!(other.is_logged_in());
+    let other_handle = other.handle();
+    drop(other);
+    assert!(ctx.get_session_info(other_handle).is_err());
+    assert_eq!(state(&session), CKS_RO_USER_FUNCTIONS);
+
+    let handle = session.handle();
+    drop(session);
+    assert!(ctx.get_session_info(handle).is_err());
+    let session = Session::open(ctx.clone(), slot_id, 0).unwrap();
+    assert_eq!(state(&session), CKS_RO_PUBLIC_SESSION);
+    drop(session);
+
+    // the session that logged in may go first, the login lasts until the last one
+    let mut owner = Session::open(ctx.clone(), slot_id, 0).unwrap();
+    owner.login(CKU_USER, Some("1234")).unwrap();
+    let mut other = Session::open(ctx.clone(), slot_id, 0).unwrap();
+    other.login(CKU_USER, Some("1234")).unwrap();
+    drop(owner);
+    assert_eq!(state(&other), CKS_RO_USER_FUNCTIONS);
+    drop(other);
+    let session = Session::open(ctx.clone(), slot_id, 0).unwrap();
+    assert_eq!(state(&session), CKS_RO_PUBLIC_SESSION);
+}
+
+#[test]
+#[cfg(feature = "runtime")]
+#[serial]
+fn softhsm_identify_objects() {
+    use crate::{IdentifyOptions, ModuleResolver, ObjectClass};
+    use pkcs11::types::{
+        CKA_CLASS, CKO_PUBLIC_KEY, CKS_RO_USER_FUNCTIONS, CKS_RW_USER_FUNCTIONS, CK_ATTRIBUTE,
+        CK_ULONG,
+    };
+
+    // the keypair of the examples, see README.md
+    let module_path = pkcs11_module_name().to_str().unwrap().to_string();
+    let builder = || {
+        Pkcs11Uri::builder()
+            .token_label("my-ca")
+            .object_label("my-signing-key")
+            .pin_value("1234")
+            .module_path(module_path.as_str())
+    };
+
+    // public and private key share the label
+    let uri = builder().build().unwrap();
+    let mut objects = uri.identify_objects().unwrap();
+    let handles: Vec<_> = objects.by_ref().collect::<anyhow::Result<_>>().unwrap();
+    assert_eq!(handles.len(), 2);
+    assert!(objects.next().is_none());
+    let session = objects.into_session();
+    assert!(session.is_logged_in());
+    drop(session);
+    let error = uri.identify_object().unwrap_err().to_string();
+    assert!(error.contains("matches 2 objects"), "{}", error);
+
+    let uri = builder()
+        .object_class(ObjectClass::PublicKey)
+        .build()
+        .unwrap();
+    let (session, object) = uri.identify_object().unwrap();
+    let state = |session: &crate::Session| {
+        let ctx = session.module();
+        ctx.get_session_info(session.handle()).unwrap().state
+    };
+    assert_eq!(state(&session), CKS_RO_USER_FUNCTIONS);
+    let class: CK_ULONG = 0;
+    let mut template = vec![CK_ATTRIBUTE::new(CKA_CLASS).with_ck_ulong(&class)];
+    let (_, attributes) = session
+        .module()
+        .get_attribute_value(session.handle(), object, &mut template)
+        .unwrap();
+    assert_eq!(attributes[0].get_ck_ulong().unwrap(), CKO_PUBLIC_KEY);
+    drop(session);
+
+    // read-write only on request
+    let options = IdentifyOptions {
+        read_write: true,
+        ..IdentifyOptions::default()
+    };
+    let (session, _) = uri
+        .identify_object_by(&ModuleResolver::new(), options)
+        .unwrap();
+    assert_eq!(state(&session), CKS_RW_USER_FUNCTIONS);
+    drop(session);
+
+    // error messages leave out the PIN
+    let uri = builder().object_label("no-such-key").build().unwrap();
+    assert_eq!(uri.identify_objects().unwrap().count(), 0);
+    let error = uri.identify_object().unwrap_err().to_string();
+    assert!(error.contains("pin-value=***"), "{}", error);
+    let uri = builder().token_label("no-such-token").build().unwrap();
+    let error = uri.identify_object().unwrap_err().to_string();
+    assert!(error.contains("pin-value=***"), "{}", error);
+}
+
+#[test]
+#[cfg(feature = "runtime")]
+#[serial]
+fn softhsm_slot_policies() {
+    use crate::{IdentifyOptions, ModuleResolver, SlotPolicy};
+
+    let by = |slot_policy| IdentifyOptions {
+        slot_policy,
+        ..IdentifyOptions::default()
+    };
+
+    let module_path = pkcs11_module_name().to_str().unwrap().to_string();
+    let resolver = ModuleResolver::new();
+
+    // SoftHSM always has a free slot besides the token of the examples, but its
+    // token is not initialized, so only the token of the examples is searched
+    let uri = Pkcs11Uri::builder()
+        .module_path(module_path.as_str())
+        .build()
+        .unwrap();
+    assert!(uri.identify_tokens().unwrap().len() > 1);
+    let my_ca = Pkcs11Uri::builder()
+        .token_label("my-ca")
+        .module_path(module_path.as_str())
+        .build()
+        .unwrap()
+        .identify_tokens()
+        .unwrap()
+        .remove(0);
+    for policy in [SlotPolicy::Unique, SlotPolicy::First, SlotPolicy::All] {
+        let searches = uri.identify_objects_by(&resolver, by(policy)).unwrap();
+        assert_eq!(searches.len(), 1);
+        assert_eq!(searches[0].slot(), my_ca);
+    }
+
+    let uri = Pkcs11Uri::builder()
+        .token_label("my-ca")
+        .object_label("my-signing-key")
+        .object_class(crate::ObjectClass::PublicKey)
+        .module_path(module_path.as_str())
+        .build()
+        .unwrap();
+    let token = uri.identify_tokens().unwrap().remove(0);
+    for policy in [SlotPolicy::Unique, SlotPolicy::First, SlotPolicy::All] {
+        let searches = uri.identify_objects_by(&resolver, by(policy)).unwrap();
+        assert_eq!(searches.len(), 1);
+        assert_eq!(searches[0].slot(), token);
+        let (session, _) = uri.identify_object_by(&resolver, by(policy)).unwrap();
+        assert_eq!(session.slot_id(), token.slot_id);
+    }
+}
+
+#[test]
+#[cfg(feature = "runtime")]
+#[serial]
+fn softhsm_login_variants() {
+    // the token of the examples, whose SO PIN is also 1234, see README.md
+    use crate::{IdentifyOptions, ModuleResolver};
+    use pkcs11::types::{CKS_RW_SO_FUNCTIONS, CKU_SO};
+
+    let uri = Pkcs11Uri::builder()
+        .token_label("my-ca")
+        .object_label("my-signing-key")
+        .object_class(crate::ObjectClass::PrivateKey)
+        .pin_value("1234")
+        .module_path(pkcs11_module_name().to_str().unwrap())
+        .build()
+        .unwrap();
+
+    // SoftHSM has no PIN pad, and its keys need no login per operation
+    let (session, key) = uri.identify_object().unwrap();
+    assert!(!session.has_protected_authentication_path().unwrap());
+    assert!(!session.always_authenticate(key).unwrap());
+    uri.authenticate(&session, key).unwrap();
+    drop(session);
+
+    let options = IdentifyOptions {
+        user_type: CKU_SO,
+        ..IdentifyOptions::default()
+    };
+    let uri = Pkcs11Uri::builder()
+        .token_label("my-ca")
+        .pin_value("1234")
+        .module_path(pkcs11_module_name().to_str().unwrap())
+        .build()
+        .unwrap();
+    let searches = uri
+        .identify_objects_by(&ModuleResolver::new(), options)
+        .unwrap();
+    let session = searches.into_iter().next().unwrap().into_session();
+    let state = session
+        .module()
+        .get_session_info(session.handle())
+        .unwrap()
+        .state;
+    assert_eq!(state, CKS_RW_SO_FUNCTIONS);
+}
+
+#[test]
+#[cfg(feature = "runtime")]
+fn pin_sources() {
+    use crate::{CommandPinProvider, PinProvider, PinProviders, SecretPin};
+    use std::time::Duration;
+
+    // a registry of its own, so other tests cannot run commands
+    let providers = PinProviders::new();
+
+    std::env::set_var("PKCS11_URI_TEST_PIN", "1234");
+    assert_eq!(
+        providers
+            .pin("env:PKCS11_URI_TEST_PIN")
+            .unwrap()
+            .expose_secret(),
+        "1234"
+    );
+    assert!(providers.pin("env:PKCS11_URI_TEST_NO_PIN").is_err());
+
+    let dir = std::env::temp_dir().join(format!("pkcs11-uri-pins-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("token pin");
+    std::fs::write(&file, "5678 \t\nsecond line\n").unwrap();
+    let path = file.to_str().unwrap();
+    let encoded = path.replace(' ', "%20");
+    assert_eq!(providers.pin(path).unwrap().expose_secret(), "5678");
+    assert_eq!(
+        providers
+            .pin(&format!("file:{}", path))
+            .unwrap()
+            .expose_secret(),
+        "5678"
+    );
+    assert_eq!(
+        providers
+            .pin(&format!("FILE://{}", encoded))
+            .unwrap()
+            .expose_secret(),
+        "5678"
+    );
+    assert_eq!(
+        providers
+            .pin(&format!("file://localhost{}", encoded))
+            .unwrap()
+            .expose_secret(),
+        "5678"
+    );
+    assert!(providers.pin(&format!("file://host{}", encoded)).is_err());
+    assert!(providers
+        .pin(&format!("file://{}.missing", encoded))
+        .is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // the URI is decoded once by the parser, and `file:` URIs once more
+    let uri = Pkcs11Uri::try_from("pkcs11:?pin-source=file:///my%2520pin").unwrap();
+    assert_eq!(
+        uri.query_attributes.pin_source.as_deref(),
+        Some("file:///my%20pin")
+    );
+
+    // commands only run once the application opts in
+    assert!(providers.pin("|echo 4321").is_err());
+    providers.register("|", CommandPinProvider::default());
+    if cfg!(unix) {
+        assert_eq!(providers.pin("|echo 4321").unwrap().expose_secret(), "4321");
+        assert!(providers.pin("|false").is_err());
+        let command = CommandPinProvider::new(Duration::from_millis(100));
+        assert!(command.pin("sleep 5").is_err());
+    }
+    assert!(providers.pin("|").is_err());
+
+    // unknown schemes are errors, until registered
+    assert!(providers.pin("prompt:Token PIN").is_err());
+    struct Prompt;
+    impl PinProvider for Prompt {
+        fn pin(&self, prompt: &str) -> anyhow::Result<SecretPin> {
+            Ok(format!("answer to {}", prompt).into())
+        }
+    }
+    providers.register("Prompt", Prompt);
+    assert_eq!(
+        providers.pin("prompt:Token PIN").unwrap().expose_secret(),
+        "answer to Token PIN"
+    );
+
+    // URIs read `pin-source` with the registry they are given
+    let uri = Pkcs11Uri::try_from("pkcs11:?pin-source=prompt:PIN").unwrap();
+    assert!(uri.pin().is_err());
+    let pin = uri.pin_with(&providers).unwrap().unwrap();
+    assert_eq!(pin.expose_secret(), "answer to PIN");
+}
+
+#[test]
+#[cfg(feature = "runtime")]
+fn module_resolution() {
+    use crate::{ModuleResolver, ModuleSource};
+    use std::fs;
+
+    let root = std::env::temp_dir().join(format!("pkcs11-uri-modules-{}", std::process::id()));
+    let (user, package, lib) = (root.join("user"), root.join("package"), root.join("lib"));
+    for dir in [&user, &package, &lib].iter() {
+        fs::create_dir_all(dir).unwrap();
+    }
+    let library = |name: &str| {
+        let path = lib.join(format!("{}{}", name, std::env::consts::DLL_SUFFIX));
+        fs::write(&path, b"").unwrap();
+        path
+    };
+    let softhsm = library("libsofthsm2");
+    let other = library("libother");
+    let standalone = library("libstandalone");
+
+    fs::write(
+        package.join("softhsm2.module"),
+        format!(
+            "# SoftHSM\nmodule: {}\n",
+            softhsm.file_name().unwrap().to_str().unwrap()
+        ),
+    )
+    .unwrap();
+    fs::write(package.join("other.module"), "module: /opt/other.so\n").unwrap();
+    fs::write(
+        user.join("other.module"),
+        format!("module: {}\ncritical: no\n", other.display()),
+    )
+    .unwrap();
+    // without `module`, the package entry stays in effect
+    fs::write(user.join("softhsm2.module"), "priority: 10\n").unwrap();
+
+    let resolver = ModuleResolver::new()
+        .config_dir(&user)
+        .config_dir(&package)
+        .library_dir(&lib);
+
+    let module = resolver.resolve("softhsm2").unwrap();
+    assert_eq!(module.path, softhsm);
+    assert_eq!(
+        module.source,
+        ModuleSource::Config(package.join("softhsm2.module"))
+    );
+    // also by library name
+    assert_eq!(resolver.resolve("libsofthsm2").unwrap().path, softhsm);
+
+    let module = resolver.resolve("other").unwrap();
+    assert_eq!(module.path, other);
+    assert_eq!(
+        module.source,
+        ModuleSource::Config(user.join("other.module"))
+    );
+
+    let module = resolver.resolve("standalone").unwrap();
+    assert_eq!(module.path, standalone);
+    assert_eq!(module.source, ModuleSource::LibraryDir(lib.clone()));
+
+    assert!(resolver.resolve("missing").is_err());
+    // names cannot reach libraries outside the library directories
+    let absolute = lib.join("libstandalone");
+    for name in [
+        absolute.to_str().unwrap(),
+        "../lib/libstandalone",
+        "..\\lib\\libstandalone",
+    ]
+    .iter()
+    .copied()
+    {
+        assert!(resolver.resolve(name).is_err(), "{}", name);
+    }
+    assert_eq!(resolver.registered_modules().len(), 2);
+
+    let uri = Pkcs11Uri::try_from("pkcs11:token=a?module-name=other").unwrap();
+    assert_eq!(uri.module_path_with(&resolver).unwrap(), other);
+    let uri = Pkcs11Uri::try_from("pkcs11:token=a?module-name=other&module-path=/x.so").unwrap();
+    assert_eq!(
+        uri.module_path_with(&resolver).unwrap(),
+        PathBuf::from("/x.so")
+    );
+    let uri = Pkcs11Uri::try_from("pkcs11:token=a?module-name=missing").unwrap();
+    assert!(uri.context_with(&resolver).is_err());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn display_round_trip() {
+    let uri_str = "pkcs11:token=The%20Software%20PKCS%2311%20Softtoken;\
+        manufacturer=Snake%20Oil,%20Inc.;serial=DECC0401648;\
+        object=my%3Bkey;type=private;id=%69%95%3E%5C%F4%BD%EC%91\
+        ?pin-source=file:/etc/token&module-path=/usr/lib/libsofthsm2.so";
+    let uri = Pkcs11Uri::try_from(uri_str).unwrap();
+    let canonical = uri.to_string();
+    assert_eq!(
+        canonical,
+        "pkcs11:manufacturer=Snake%20Oil,%20Inc.;token=The%20Software%20PKCS%2311%20Softtoken;\
+            serial=DECC0401648;type=private;id=%69%95%3E%5C%F4%BD%EC%91;object=my%3Bkey\
+            ?pin-source=file:/etc/token&module-path=/usr/lib/libsofthsm2.so"
+    );
+
+    let reparsed = Pkcs11Uri::try_from(canonical.as_str()).unwrap();
+    assert_eq!(reparsed.path_attributes, uri.path_attributes);
+    assert_eq!(reparsed.query_attributes, uri.query_attributes);
+    assert_eq!(reparsed.to_string(), canonical);
+}
+
+#[test]
+fn display_library_version() {
+    let uri =
+        Pkcs11Uri::try_from("pkcs11:library-version=3;slot-id=327?module-name=softhsm2").unwrap();
+    assert_eq!(
+        uri.to_string(),
+        "pkcs11:library-version=3.0;slot-id=327?module-name=softhsm2"
+    );
+}
+
+#[test]
+fn parse_errors() {
+    use crate::Pkcs11UriError::*;
+
+    let error = Pkcs11Uri::try_from("https://example.com").unwrap_err();
+    assert_eq!(error, WrongScheme { offset: 0 });
+
+    let error = Pkcs11Uri::try_from("pkcs11:token=a;colour=blue?pin-value=1").unwrap_err();
+    assert_eq!(error.attribute(), Some("colour"));
+    assert_eq!(error.offset(), 15);
+    assert!(matches!(error, UnknownAttribute { .. }));
+
+    let error = Pkcs11Uri::try_from("pkcs11:token=a;token=b?pin-value=1").unwrap_err();
+    assert!(matches!(error, DuplicateAttribute { offset: 15, .. }));
+
+    let error = Pkcs11Uri::try_from("pkcs11:slot-id=x1?pin-value=1").unwrap_err();
+    assert!(matches!(error, InvalidSlotId { offset: 15, .. }));
+
+    let error = Pkcs11Uri::try_from("pkcs11:serial=0123456789abcdefg?pin-value=1").unwrap_err();
+    assert!(matches!(error, SerialTooLong { .. }));
+
+    let error = QueryAttributes::try_from("pin-value=1&module-path=%2").unwrap_err();
+    assert_eq!(
+        error,
+        InvalidPercentEncoding {
+            attribute: "module-path".into(),
+            offset: 24
+        }
+    );
+
+    // offsets refer to the original input, including whitespace
+    let error = Pkcs11Uri::try_from("pkcs11:\n    token=a;\n    x=y?pin-value=1").unwrap_err();
+    assert_eq!(error.offset(), 25);
+}
+
+#[test]
+fn malformed_uris_do_not_panic() {
+    let inputs = [
+        "",
+        "pkcs11",
+        "pkcs11:token",
+        "pkcs11:token=a;;object=b?pin-value=1",
+        "pkcs11:token=a?pin-value",
+        "pkcs11:library-version=1.2.3?pin-value=1",
+        "pkcs11:library-version=.1?pin-value=1",
+        "pkcs11:library-version=256?pin-value=1",
+        "pkcs11:type=secret?pin-value=1",
+        "pkcs11:object=%FF%FE?pin-value=1",
+        "pkcs11:slot-id=99999999999999999999999?pin-value=1",
+        "pkcs11:token=a/b?pin-value=1",
+        "pkcs11://authority/token=a",
+    ];
+    for input in inputs.iter().copied() {
+        assert!(Pkcs11Uri::try_from(input).is_err(), "{}", input);
+    }
+}
+
+#[test]
+#[cfg(feature = "runtime")]
+fn failing_modules_do_not_panic() {
+    // no module given, and none to search
+    let uri = Pkcs11Uri::try_from("pkcs11:token=my-ca?pin-value=1234").unwrap();
+    let resolver = crate::ModuleResolver::new();
+    assert!(uri.context().is_err());
+    assert!(uri.identify_slots_with(&resolver).is_err());
+    assert!(uri.identify_object_with(&resolver).is_err());
+    let resolver = resolver.search_module("/nonexistent/libpkcs11.so");
+    assert!(uri.identify_tokens_with(&resolver).is_err());
+
+    let uri =
+        Pkcs11Uri::try_from("pkcs11:token=my-ca?module-path=/nonexistent/libpkcs11.so").unwrap();
+    assert!(uri.context().is_err());
+    assert!(uri.identify_tokens().is_err());
+    assert!(uri.identify_object().is_err());
+}
+
+/// Example URIs from section 3.3 of RFC 7512
+const RFC_7512_EXAMPLES: &[&str] = &[
+    "pkcs11:",
+    "pkcs11:object=my-pubkey;type=public",
+    "pkcs11:object=my-key;type=private?pin-source=file:/etc/token",
+    "pkcs11:token=The%20Software%20PKCS%2311%20Softtoken;\
+        manufacturer=Snake%20Oil,%20Inc.;model=1.0;object=my-certificate;\
+        type=cert;id=%69%95%3E%5C%F4%BD%EC%91;serial=\
+        ?pin-source=file:/etc/token_pin",
+    "pkcs11:object=my-sign-key;type=private?module-name=mypkcs11",
+    "pkcs11:object=my-sign-key;type=private?module-path=/mnt/libmypkcs11.so.1",
+    "pkcs11:token=Software%20PKCS%2311%20softtoken;\
+        manufacturer=Snake%20Oil,%20Inc.?pin-value=the-pin",
+    "pkcs11:slot-description=Sun%20Metaslot",
+    "pkcs11:library-manufacturer=Snake%20Oil,%20Inc.;\
+        library-description=Soft%20Token%20Library;library-version=1.23",
+    "pkcs11:token=My%20token%25%20created%20by%20Joe;library-version=3;\
+        id=%69%95%3E%5C%F4%BD%EC%91;object=my-certificate;type=cert;serial=\
+        ?pin-source=file:/etc/token",
+    "pkcs11:token=A%20name%20with%20a%20substring%20%25%3B;\
+        object=my-certificate;type=cert",
+    "pkcs11:token=Name%20with%20a%20small%20A%20with%20acute:%20%C3%A1;\
+        object=my-certificate;type=cert",
+];
+
+#[test]
+fn rfc_7512_examples() {
+    for example in RFC_7512_EXAMPLES.iter().copied() {
+        let uri = Pkcs11Uri::try_from(example).unwrap_or_else(|e| panic!("{}: {}", example, e));
+        let reparsed = Pkcs11Uri::try_from(uri.to_string().as_str()).unwrap();
+        assert_eq!(reparsed.path_attributes, uri.path_attributes);
+        assert_eq!(reparsed.query_attributes, uri.query_attributes);
+    }
+
+    let uri = Pkcs11Uri::try_from(RFC_7512_EXAMPLES[11]).unwrap();
+    assert_eq!(
+        uri.path_attributes.token_label.as_deref(),
+        Some("Name with a small A with acute: \u{e1}")
+    );
+    let uri = Pkcs11Uri::try_from(RFC_7512_EXAMPLES[8]).unwrap();
+    assert_eq!(
+        uri.path_attributes.library_version,
+        Some(crate::Version {
+            major: 1,
+            minor: 23
+        })
+    );
+}
+
+#[test]
+fn empty_components_and_values() {
+    let uri = Pkcs11Uri::try_from("pkcs11:").unwrap();
+    assert_eq!(uri.path_attributes, Default::default());
+    assert_eq!(uri.query_attributes, Default::default());
+    assert_eq!(uri.to_string(), "pkcs11:");
+
+    let uri = Pkcs11Uri::try_from("pkcs11:?").unwrap();
+    assert_eq!(uri.query_attributes, Default::default());
+
+    let uri = Pkcs11Uri::try_from("pkcs11:?module-path=/x.so").unwrap();
+    assert_eq!(uri.query_attributes.module_path.as_deref(), Some("/x.so"));
+
+    let uri = Pkcs11Uri::try_from("pkcs11:token=").unwrap();
+    assert_eq!(uri.path_attributes.token_label.as_deref(), Some(""));
+
+    let uri = Pkcs11Uri::try_from("pkcs11:object=a=b?pin-source=|/bin/pin?x").unwrap();
+    assert_eq!(uri.path_attributes.object_label.as_deref(), Some("a=b"));
+    assert_eq!(
+        uri.query_attributes.pin_source.as_deref(),
+        Some("|/bin/pin?x")
+    );
+}
+
+#[test]
+fn grammar_violations() {
+    let inputs = [
+        "pkcs11:;token=a",
+        "pkcs11:token=a?&pin-value=1",
+        "pkcs11:slot-id=",
+        "pkcs11:slot-id=+1",
+        "pkcs11:library-version=",
+        "pkcs11:library-version=1.",
+        "pkcs11:library-version=+1",
+        "pkcs11:?module-name=",
+        "pkcs11:?module-path=",
+        "pkcs11:?pin-value=a;b",
+        "pkcs11:token=a#fragment",
+        "pkcs11:token=%ZZ",
+    ];
+    for input in inputs.iter().copied() {
+        assert!(Pkcs11Uri::try_from(input).is_err(), "{}", input);
+    }
+}
+
+#[test]
+fn parse_options() {
+    use crate::ParseOptions;
+    use crate::Pkcs11UriError::*;
+
+    let strict = ParseOptions::strict();
+    let lenient = ParseOptions::lenient();
+
+    let folded = "pkcs11:token=my-ca;\r\n    object=my-key\n?pin-value=1234\n";
+    let error = Pkcs11Uri::parse_with(folded, strict).unwrap_err();
+    assert_eq!(error.offset(), 19);
+    let uri = Pkcs11Uri::parse_with(folded, lenient).unwrap();
+    assert_eq!(uri.path_attributes.object_label.as_deref(), Some("my-key"));
+    assert_eq!(
+        uri.to_string(),
+        "pkcs11:token=my-ca;object=my-key?pin-value=1234"
+    );
+
+    // whitespace inside values is never removed
+    for options in [strict, lenient].iter().copied() {
+        let error = Pkcs11Uri::parse_with("pkcs11:object=my key", options).unwrap_err();
+        assert!(matches!(error, InvalidCharacter { offset: 16, .. }));
+    }
+    // but line breaks may split them, as in RFC 3986 appendix C
+    let uri = Pkcs11Uri::parse_with("pkcs11:object=my\n  key", lenient).unwrap();
+    assert_eq!(uri.path_attributes.object_label.as_deref(), Some("mykey"));
+
+    let error = Pkcs11Uri::parse_with("pkcs11:id=%0a", strict).unwrap_err();
+    assert!(matches!(error, InvalidPercentEncoding { offset: 10, .. }));
+    let uri = Pkcs11Uri::parse_with("pkcs11:id=%0a%0A", lenient).unwrap();
+    assert_eq!(uri.path_attributes.object_id, Some(vec![10, 10]));
+
+    for input in [
+        "pkcs11:token=a;",
+        "pkcs11:token=a;?pin-value=1",
+        "pkcs11:?pin-value=1&",
+    ]
+    .iter()
+    .copied()
+    {
+        let error = Pkcs11Uri::parse_with(input, strict).unwrap_err();
+        assert!(matches!(error, InvalidSyntax { .. }), "{}", input);
+        assert!(Pkcs11Uri::parse_with(input, lenient).is_ok(), "{}", input);
+    }
+    assert!(Pkcs11Uri::try_from("pkcs11:token=a;;object=b").is_err());
+
+    assert!(crate::Pkcs11UriRef::parse_with("pkcs11:token=a;", lenient).is_ok());
+    assert!(crate::Pkcs11UriRef::parse_with("pkcs11:token=a;", strict).is_err());
+
+    // RFC 7512 only names five object classes
+    assert!(Pkcs11Uri::parse_with("pkcs11:type=secret-key", strict).is_ok());
+    for input in [
+        "pkcs11:token=a;type=otp-key",
+        "pkcs11:token=a;type=0x80000001",
+    ]
+    .iter()
+    .copied()
+    {
+        let error = Pkcs11Uri::parse_with(input, strict).unwrap_err();
+        assert!(
+            matches!(error, InvalidValue { offset: 20, .. }),
+            "{}",
+            input
+        );
+        assert!(Pkcs11Uri::parse_with(input, lenient).is_ok(), "{}", input);
+    }
+}
+
+#[test]
+fn slashes_in_values() {
+    use crate::Pkcs11UriError::*;
+
+    // `/` must be escaped in the path, but not in the query
+    let error = Pkcs11Uri::try_from("pkcs11:token=a;object=b/c").unwrap_err();
+    assert_eq!(
+        error,
+        InvalidCharacter {
+            attribute: "object".into(),
+            offset: 23
+        }
+    );
+    let uri = Pkcs11Uri::try_from("pkcs11:object=b%2Fc?module-path=/usr/lib/p11/x.so").unwrap();
+    assert_eq!(uri.path_attributes.object_label.as_deref(), Some("b/c"));
+    assert_eq!(
+        uri.query_attributes.module_path.as_deref(),
+        Some("/usr/lib/p11/x.so")
+    );
+
+    // so are `?` and `|`, and only the first `?` starts the query
+    let uri = Pkcs11Uri::try_from("pkcs11:?pin-source=|/bin/pin?a&module-name=p11").unwrap();
+    assert_eq!(
+        uri.query_attributes.pin_source.as_deref(),
+        Some("|/bin/pin?a")
+    );
+    assert_eq!(uri.query_attributes.module_name.as_deref(), Some("p11"));
+
+    let error = Pkcs11Uri::try_from("pkcs11:/token=a").unwrap_err();
+    assert_eq!(error.offset(), 7);
+}
+
+#[test]
+fn vendor_attributes() {
+    // the last example of RFC 7512 section 3.3, with the `x-` prefix the grammar requires
+    let uri = Pkcs11Uri::try_from(
+        "pkcs11:token=my-token;object=my-certificate;type=cert;x-vendor-aaa=value-a\
+            ?pin-source=file:/etc/token&x-vendor-bbb=value-b",
+    )
+    .unwrap();
+    assert_eq!(
+        uri.path_attributes
+            .vendor
+            .get("x-vendor-aaa")
+            .map(Vec::as_slice),
+        Some(&b"value-a"[..])
+    );
+    assert_eq!(
+        uri.query_attributes
+            .vendor
+            .get("x-vendor-bbb")
+            .map(Vec::as_slice),
+        Some(&b"value-b"[..])
+    );
+
+    let uri = Pkcs11Uri::try_from("pkcs11:x-b=%00%01;token=t;x-a=a%20b?x-c=").unwrap();
+    assert_eq!(uri.path_attributes.vendor["x-b"], [0, 1]);
+    assert_eq!(uri.query_attributes.vendor["x-c"], b"");
+    assert_eq!(uri.to_string(), "pkcs11:token=t;x-a=a%20b;x-b=%00%01?x-c=");
+
+    for input in [
+        "pkcs11:x-=a",
+        "pkcs11:x-a.b=c",
+        "pkcs11:x-a=1;x-a=2",
+        "pkcs11:y-a=1",
+    ]
+    .iter()
+    .copied()
+    {
+        assert!(Pkcs11Uri::try_from(input).is_err(), "{}", input);
+    }
+}
+
+#[test]
+fn object_classes() {
+    use crate::ObjectClass::{self, *};
+    use crate::{
+        CkUlong, CKO_CERTIFICATE, CKO_DATA, CKO_DOMAIN_PARAMETERS, CKO_HW_FEATURE, CKO_MECHANISM,
+        CKO_OTP_KEY, CKO_PRIVATE_KEY, CKO_PROFILE, CKO_PUBLIC_KEY, CKO_SECRET_KEY,
+        CKO_VENDOR_DEFINED,
+    };
+
+    let classes = [
+        (Data, "data", CKO_DATA),
+        (Certificate, "cert", CKO_CERTIFICATE),
+        (PublicKey, "public", CKO_PUBLIC_KEY),
+        (PrivateKey, "private", CKO_PRIVATE_KEY),
+        (SecretKey, "secret-key", CKO_SECRET_KEY),
+        (HwFeature, "hw-feature", CKO_HW_FEATURE),
+        (DomainParameters, "domain-parameters", CKO_DOMAIN_PARAMETERS),
+        (Mechanism, "mechanism", CKO_MECHANISM),
+        (OtpKey, "otp-key", CKO_OTP_KEY),
+        (Profile, "profile", CKO_PROFILE),
+        (Vendor(0x8000_0001), "0x80000001", CKO_VENDOR_DEFINED + 1),
+    ];
+    for (class, name, value) in classes.iter().copied() {
+        assert_eq!(ObjectClass::try_from(name), Ok(class));
+        assert_eq!(class.to_string(), name);
+        assert_eq!(CkUlong::try_from(class), Ok(value));
+        assert_eq!(ObjectClass::try_from(value), Ok(class));
+    }
+
+    assert_eq!(ObjectClass::try_from(10 as CkUlong), Err(10));
+    // `CK_ULONG` is 32 bits wide on Windows
+    assert_eq!(
+        CkUlong::try_from(Vendor(0x1_0000_0000)).is_err(),
+        cfg!(windows)
+    );
+    for name in ["0x10", "0x", "0xfoo", "otp", "Private"].iter().copied() {
+        assert!(ObjectClass::try_from(name).is_err(), "{}", name);
+    }
+
+    let uri = Pkcs11Uri::try_from("pkcs11:type=otp-key").unwrap();
+    assert_eq!(uri.path_attributes.object_class, Some(OtpKey));
+}
+
+#[test]
+fn equivalence() {
+    use std::collections::HashSet;
+
+    let equivalent = [
+        "pkcs11:token=my%20token;object=my-key;type=private?pin-source=file:/etc/token",
+        "pkcs11:type=private;object=my-key;token=my%20token?pin-source=file:/etc/token",
+        "pkcs11:token=my%20token;object=my%2dkey;type=private?pin-source=file%3a/etc/token",
+        "pkcs11:token=%6d%79%20token;object=%6D%79-key;type=private?pin-source=file:/etc/token",
+        "PKCS11:token=my%20token;object=my-key;type=private?pin-source=file:/etc/token",
+    ];
+    let uris: Vec<Pkcs11Uri> = equivalent
+        .iter()
+        .copied()
+        .map(|uri| Pkcs11Uri::try_from(uri).unwrap())
+        .collect();
+    let unique: HashSet<&Pkcs11Uri> = uris.iter().collect();
+    assert_eq!(unique.len(), 1);
+    for uri in &uris {
+        assert_eq!(uri, &uris[0]);
+        assert_eq!(uri.cmp(&uris[0]), core::cmp::Ordering::Equal);
+        assert_eq!(uri.to_string(), uris[0].to_string());
+    }
+
+    let a = Pkcs11Uri::try_from("pkcs11:library-version=1").unwrap();
+    let b = Pkcs11Uri::try_from("pkcs11:library-version=1.0").unwrap();
+    assert_eq!(a, b);
+
+    let c = Pkcs11Uri::try_from("pkcs11:token=my%20Token").unwrap();
+    assert_ne!(c, Pkcs11Uri::try_from("pkcs11:token=my%20token").unwrap());
+}
+
+#[test]
+fn builder() {
+    use crate::{ObjectClass, Pkcs11UriError, Version};
+
+    let uri = Pkcs11Uri::builder()
+        .library_version(Version {
+            major: 2,
+            minor: 40,
+        })
+        .token_label("my token")
+        .token_serial(b"DECC0401648")
+        .object_id(vec![0x69, 0x95, 0x3e])
+        .object_class(ObjectClass::PrivateKey)
+        .path_vendor_attribute("x-partition", &b"a;b"[..])
+        .pin_source("file:/etc/token")
+        .module_path("/usr/lib/libsofthsm2.so")
+        .build()
+        .unwrap();
+    assert_eq!(
+        uri.to_string(),
+        "pkcs11:library-version=2.40;token=my%20token;serial=DECC0401648;\
+            type=private;id=%69%95%3E;x-partition=a%3Bb\
+            ?pin-source=file:/etc/token&module-path=/usr/lib/libsofthsm2.so"
+    );
+    assert_eq!(uri, Pkcs11Uri::try_from(uri.to_string().as_str()).unwrap());
+
+    let error = Pkcs11Uri::builder()
+        .token_serial([0u8; 17])
+        .build()
+        .unwrap_err();
+    assert!(matches!(error, Pkcs11UriError::SerialTooLong { .. }));
+
+    let error = Pkcs11Uri::builder().module_name("").build().unwrap_err();
+    assert_eq!(error.attribute(), Some("module-name"));
+
+    let error = Pkcs11Uri::builder()
+        .query_vendor_attribute("vendor", "value")
+        .build()
+        .unwrap_err();
+    assert!(matches!(error, Pkcs11UriError::UnknownAttribute { .. }));
+
+    // classes below `CKO_VENDOR_DEFINED` would not parse back
+    let error = Pkcs11Uri::builder()
+        .object_class(ObjectClass::Vendor(1))
+        .build()
+        .unwrap_err();
+    assert_eq!(error.attribute(), Some("type"));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde() {
+    use crate::{ObjectClass, PathAttributes};
+
+    let uri_str = "pkcs11:token=my%20token;type=private?module-name=softhsm2";
+    let uri = Pkcs11Uri::try_from(uri_str).unwrap();
+    let json = serde_json::to_string(&uri).unwrap();
+    assert_eq!(json, format!("\"{}\"", uri_str));
+    assert_eq!(serde_json::from_str::<Pkcs11Uri>(&json).unwrap(), uri);
+    assert!(serde_json::from_str::<Pkcs11Uri>("\"pkcs11:colour=blue\"").is_err());
+
+    let json = serde_json::to_string(&uri.path_attributes).unwrap();
+    assert_eq!(json, r#"{"token":"my token","type":"private"}"#);
+    let path_attributes: PathAttributes =
+        serde_json::from_str(r#"{"type":"0x80000001","library-version":{"major":3,"minor":1}}"#)
+            .unwrap();
+    assert_eq!(
+        path_attributes.object_class,
+        Some(ObjectClass::Vendor(0x8000_0001))
+    );
+    assert_eq!(path_attributes.library_version.unwrap().minor, 1);
+
+    // the structured form is checked like the builder checks, so it displays as a URI
+    let path_attributes: PathAttributes =
+        serde_json::from_str(r#"{"vendor":{"x-partition":[1]}}"#).unwrap();
+    assert_eq!(path_attributes.to_string(), "x-partition=%01");
+    assert!(serde_json::from_str::<PathAttributes>(r#"{"vendor":{"bad name":[1]}}"#).is_err());
+    assert!(serde_json::from_str::<QueryAttributes>(r#"{"module-path":""}"#).is_err());
+    assert!(serde_json::from_str::<QueryAttributes>(r#"{"module-name":""}"#).is_err());
+}
+
+#[test]
+fn pins_are_redacted() {
+    use crate::{Pkcs11UriRef, SecretPin};
+
+    let uri_str = "pkcs11:token=my-ca?pin-value=s3cr%3Et&module-name=softhsm2";
+    let uri = Pkcs11Uri::try_from(uri_str).unwrap();
+    let pin = uri.query_attributes.pin_value.as_ref().unwrap();
+    assert_eq!(pin.expose_secret(), "s3cr>t");
+    assert_eq!(format!("{:?}", pin), "SecretPin(***)");
+
+    let borrowed = Pkcs11UriRef::try_from(uri_str).unwrap();
+    for debug in [
+        format!("{:?}", uri),
+        format!("{:#?}", uri.query_attributes),
+        format!("{:?}", borrowed),
+        format!("{:?}", borrowed.query_attributes),
+    ]
+    .iter()
+    {
+        assert!(!debug.contains("s3cr"), "{}", debug);
+        assert!(debug.contains("softhsm2"), "{}", debug);
+    }
+
+    // the canonical form keeps the PIN
+    assert_eq!(uri.to_string(), uri_str);
+    let built = Pkcs11Uri::builder().pin_value("s3cr>t").build().unwrap();
+    assert_eq!(
+        built.query_attributes.pin_value,
+        Some(SecretPin::from("s3cr>t"))
+    );
+}
+
+#[test]
+fn redacted() {
+    use crate::RedactOptions;
+
+    let uri = Pkcs11Uri::try_from(
+        "pkcs11:token=my-ca;object=my-key\
+            ?pin-source=file:/etc/token&pin-value=1234&module-name=softhsm2\
+            &module-path=/usr/lib/libsofthsm2.so&x-a=b",
+    )
+    .unwrap();
+    assert_eq!(
+        uri.redacted(),
+        "pkcs11:token=my-ca;object=my-key?pin-source=file:/etc/token&pin-value=***\
+            &module-name=softhsm2&module-path=/usr/lib/libsofthsm2.so&x-a=b"
+    );
+    assert_eq!(
+        uri.redacted_with(RedactOptions::shareable()),
+        "pkcs11:token=my-ca;object=my-key?pin-value=***&module-name=softhsm2&x-a=b"
+    );
+    let options = RedactOptions {
+        strip_module_path: true,
+        ..RedactOptions::default()
+    };
+    assert_eq!(
+        uri.redacted_with(options),
+        "pkcs11:token=my-ca;object=my-key?pin-source=file:/etc/token&pin-value=***\
+            &module-name=softhsm2&x-a=b"
+    );
+    assert_eq!(
+        uri.redacted_with(RedactOptions::path_only()),
+        "pkcs11:token=my-ca;object=my-key"
+    );
+
+    // without secrets, nothing changes
+    let uri = Pkcs11Uri::try_from("pkcs11:object=my-key?module-name=softhsm2").unwrap();
+    assert_eq!(uri.redacted(), uri.to_string());
+    let redacted = uri.redacted_with(RedactOptions::shareable());
+    assert_eq!(Pkcs11Uri::try_from(redacted.as_str()).unwrap(), uri);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn serde_redacts_pins() {
+    let uri = Pkcs11Uri::try_from("pkcs11:token=my-ca?pin-value=1234").unwrap();
+    let json = serde_json::to_string(&uri).unwrap();
+    assert_eq!(json, r#""pkcs11:token=my-ca?pin-value=***""#);
+    let json = serde_json::to_string(&uri.query_attributes).unwrap();
+    assert_eq!(json, r#"{"pin-value":"***"}"#);
+    let query_attributes: QueryAttributes =
+        serde_json::from_str(r#"{"pin-value":"1234"}"#).unwrap();
+    assert_eq!(query_attributes, uri.query_attributes);
+
+    // the placeholder is not taken for the PIN
+    let err =
+        serde_json::from_str::<Pkcs11Uri>(r#""pkcs11:token=my-ca?pin-value=***""#).unwrap_err();
+    assert!(err.to_string().contains("redacted"));
+    assert!(serde_json::from_str::<QueryAttributes>(r#"{"pin-value":"***"}"#).is_err());
+    assert!(serde_json::from_str::<crate::SecretPin>(r#""***""#).is_err());
+    let json = serde_json::to_string(&uri.to_string()).unwrap();
+    assert_eq!(serde_json::from_str::<Pkcs11Uri>(&json).unwrap(), uri);
+}
+
+#[test]
+fn borrowed() {
+    use crate::Pkcs11UriRef;
+    use std::borrow::Cow;
+
+    for example in RFC_7512_EXAMPLES.iter().copied() {
+        let borrowed = Pkcs11UriRef::try_from(example).unwrap();
+        assert_eq!(borrowed.to_owned(), Pkcs11Uri::try_from(example).unwrap());
+    }
+
+    let uri_str = "pkcs11:token=my%20token;object=my-key;serial=DECC0401648;slot-id=3;\
+        x-a=%01?module-path=/usr/lib/libsofthsm2.so&pin-source=|/bin/pin";
+    let uri = Pkcs11UriRef::try_from(uri_str).unwrap();
+    assert_eq!(uri.as_str(), uri_str);
+    assert!(matches!(
+        uri.path_attributes.object_label(),
+        Some(Cow::Borrowed("my-key"))
+    ));
+    assert!(matches!(
+        uri.path_attributes.token_label(),
+        Some(Cow::Owned(_))
+    ));
+    assert_eq!(uri.path_attributes.token_label().unwrap(), "my token");
+    assert_eq!(uri.path_attributes.slot_id(), Some(3));
+    assert_eq!(
+        &uri.path_attributes.token_serial().unwrap(),
+        b"DECC0401648     "
+    );
+    assert_eq!(uri.path_attributes.object_id(), None);
+    assert_eq!(
+        uri.query_attributes.module_path().unwrap(),
+        "/usr/lib/libsofthsm2.so"
+    );
+    let vendor: Vec<_> = uri.path_attributes.vendor().collect();
+    assert_eq!(vendor, [("x-a", Cow::Borrowed(&[1u8][..]))]);
+
+    let error = Pkcs11UriRef::try_from("pkcs11:token=a;token=b").unwrap_err();
+    assert!(matches!(
+        error,
+        crate::Pkcs11UriError::DuplicateAttribute { offset: 15, .. }
+    ));
+    let error = Pkcs11UriRef::try_from("pkcs11:x-a=1;x-a=2").unwrap_err();
+    assert!(matches!(
+        error,
+        crate::Pkcs11UriError::DuplicateAttribute { offset: 13, .. }
+    ));
+    assert!(Pkcs11UriRef::try_from("pkcs11:object=%C3").is_err());
+    assert!(Pkcs11UriRef::try_from("pkcs11:object=my key").is_err());
+    assert!(Pkcs11UriRef::try_from("pkcs11://token=a").is_err());
+}